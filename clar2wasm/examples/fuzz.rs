@@ -0,0 +1,46 @@
+//! Differential fuzzer which runs random well-typed Clarity contracts through
+//! both the compiler and the interpreter, and stops at the first divergence,
+//! shrunk down to a minimal diverging contract.
+//!
+//! ```sh
+//! cargo run --release --features developer-mode --example fuzz -- --cases 100000
+//! ```
+
+#[path = "../tests/wasm-generation/programs.rs"]
+mod programs;
+
+use clap::Parser;
+use proptest::test_runner::{Config, TestCaseError, TestError, TestRunner};
+
+#[derive(Parser)]
+#[command(name = "fuzz")]
+struct Args {
+    /// Number of contracts to generate
+    #[arg(long, default_value_t = 1_000_000)]
+    cases: u32,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let mut runner = TestRunner::new(Config {
+        cases: args.cases,
+        failure_persistence: None,
+        ..Config::default()
+    });
+    let result = runner.run(&programs::prop_program(), |program| {
+        programs::check_program(&program).map_err(TestCaseError::fail)
+    });
+
+    match result {
+        Ok(()) => println!("No divergence in {} contracts", args.cases),
+        Err(TestError::Fail(reason, program)) => {
+            eprintln!("Minimal diverging contract:\n{program}\n\n{reason}");
+            std::process::exit(1);
+        }
+        Err(TestError::Abort(reason)) => {
+            eprintln!("Fuzzing aborted: {reason}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,331 @@
+//! The `abi` module describes the interface of a compiled contract, embedded
+//! in the generated module as the `clarity-abi` custom section, so that a
+//! host can call the contract without analyzing it again.
+//!
+//! The section is the JSON serialization of a `ContractAbi`. Clarity types
+//! are written in Clarity syntax, and their Wasm lowering as the list of the
+//! value types of the flattened parameters or results.
+
+use clarity::vm::analysis::ContractAnalysis;
+use clarity::vm::types::{FunctionType, TypeSignature};
+use clarity::vm::ClarityName;
+use serde::{Deserialize, Serialize};
+use walrus::{Module, RawCustomSection, ValType};
+
+use crate::wasm_generator::{clar2wasm_ty, FunctionKind, GeneratorError};
+
+/// Name of the custom section holding the ABI.
+pub const ABI_SECTION: &str = "clarity-abi";
+
+/// Version of the format of the ABI section.
+pub const ABI_VERSION: u32 = 1;
+
+/// A Clarity type, with its lowering to Wasm value types.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AbiType {
+    /// The Clarity type, e.g. `(response uint int)`.
+    pub clarity: String,
+    /// The Wasm value types, e.g. `["i32", "i64", "i64", "i64", "i64"]`.
+    pub wasm: Vec<String>,
+}
+
+impl AbiType {
+    fn new(ty: &TypeSignature) -> Self {
+        AbiType {
+            clarity: ty.to_string(),
+            wasm: clar2wasm_ty(ty).into_iter().map(wasm_ty_name).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AbiArgument {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: AbiType,
+}
+
+/// A function of the contract. The Wasm parameters of the function are the
+/// lowerings of its arguments, in order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AbiFunction {
+    pub name: String,
+    pub kind: FunctionKind,
+    pub args: Vec<AbiArgument>,
+    pub returns: AbiType,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AbiDataVar {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: AbiType,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AbiMap {
+    pub name: String,
+    pub key: AbiType,
+    pub value: AbiType,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AbiNonFungibleToken {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: AbiType,
+}
+
+/// The interface of a compiled contract.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContractAbi {
+    pub version: u32,
+    pub contract: String,
+    pub functions: Vec<AbiFunction>,
+    pub data_vars: Vec<AbiDataVar>,
+    pub maps: Vec<AbiMap>,
+    pub fungible_tokens: Vec<String>,
+    pub non_fungible_tokens: Vec<AbiNonFungibleToken>,
+    /// The traits implemented by the contract, as
+    /// `<contract identifier>.<trait name>`.
+    pub implemented_traits: Vec<String>,
+}
+
+fn wasm_ty_name(ty: ValType) -> String {
+    match ty {
+        ValType::I32 => "i32",
+        ValType::I64 => "i64",
+        ValType::F32 => "f32",
+        ValType::F64 => "f64",
+        ValType::V128 => "v128",
+        ValType::Externref => "externref",
+        ValType::Funcref => "funcref",
+    }
+    .to_owned()
+}
+
+impl ContractAbi {
+    /// Describe the contract analyzed in `contract_analysis`.
+    pub fn new(contract_analysis: &ContractAnalysis) -> Result<Self, GeneratorError> {
+        let mut functions = Vec::new();
+        for (kind, function_types) in [
+            (
+                FunctionKind::Public,
+                &contract_analysis.public_function_types,
+            ),
+            (
+                FunctionKind::ReadOnly,
+                &contract_analysis.read_only_function_types,
+            ),
+            (
+                FunctionKind::Private,
+                &contract_analysis.private_function_types,
+            ),
+        ] {
+            for (name, function_type) in function_types {
+                functions.push(Self::function(name, kind, function_type)?);
+            }
+        }
+
+        Ok(ContractAbi {
+            version: ABI_VERSION,
+            contract: contract_analysis.contract_identifier.to_string(),
+            functions,
+            data_vars: contract_analysis
+                .persisted_variable_types
+                .iter()
+                .map(|(name, ty)| AbiDataVar {
+                    name: name.to_string(),
+                    ty: AbiType::new(ty),
+                })
+                .collect(),
+            maps: contract_analysis
+                .map_types
+                .iter()
+                .map(|(name, (key, value))| AbiMap {
+                    name: name.to_string(),
+                    key: AbiType::new(key),
+                    value: AbiType::new(value),
+                })
+                .collect(),
+            fungible_tokens: contract_analysis
+                .fungible_tokens
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            non_fungible_tokens: contract_analysis
+                .non_fungible_tokens
+                .iter()
+                .map(|(name, ty)| AbiNonFungibleToken {
+                    name: name.to_string(),
+                    ty: AbiType::new(ty),
+                })
+                .collect(),
+            implemented_traits: contract_analysis
+                .implemented_traits
+                .iter()
+                .map(|trait_id| format!("{}.{}", trait_id.contract_identifier, trait_id.name))
+                .collect(),
+        })
+    }
+
+    fn function(
+        name: &ClarityName,
+        kind: FunctionKind,
+        function_type: &FunctionType,
+    ) -> Result<AbiFunction, GeneratorError> {
+        let FunctionType::Fixed(fixed) = function_type else {
+            return Err(GeneratorError::TypeError(format!(
+                "expected fixed function type for {name}"
+            )));
+        };
+        Ok(AbiFunction {
+            name: name.to_string(),
+            kind,
+            args: fixed
+                .args
+                .iter()
+                .map(|arg| AbiArgument {
+                    name: arg.name.to_string(),
+                    ty: AbiType::new(&arg.signature),
+                })
+                .collect(),
+            returns: AbiType::new(&fixed.returns),
+        })
+    }
+
+    /// The function named `name`, if any.
+    pub fn function_named(&self, name: &str) -> Option<&AbiFunction> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+
+    /// Add the ABI section to `module`.
+    pub fn add_to_module(&self, module: &mut Module) -> Result<(), GeneratorError> {
+        let data = serde_json::to_vec(self)
+            .map_err(|e| GeneratorError::InternalError(format!("failed to serialize ABI: {e}")))?;
+        module.customs.add(RawCustomSection {
+            name: ABI_SECTION.to_owned(),
+            data,
+        });
+        Ok(())
+    }
+
+    /// Parse the content of the ABI section.
+    pub fn from_section(data: &[u8]) -> Result<Self, GeneratorError> {
+        // Check the version first, in case a later version changes the rest
+        // of the format.
+        #[derive(Deserialize)]
+        struct Version {
+            version: u32,
+        }
+        let Version { version } = serde_json::from_slice(data)
+            .map_err(|e| GeneratorError::InternalError(format!("invalid ABI section: {e}")))?;
+        if version != ABI_VERSION {
+            return Err(GeneratorError::InternalError(format!(
+                "unsupported ABI version {version}"
+            )));
+        }
+        serde_json::from_slice(data)
+            .map_err(|e| GeneratorError::InternalError(format!("invalid ABI section: {e}")))
+    }
+
+    /// Read the ABI of a generated module, if it has one.
+    pub fn from_module(module: &Module) -> Result<Option<Self>, GeneratorError> {
+        module
+            .customs
+            .iter()
+            .find(|(_, section)| section.name() == ABI_SECTION)
+            .map(|(_, section)| Self::from_section(&section.data(&Default::default())))
+            .transpose()
+    }
+
+    /// Read the ABI of the binary module `wasm`, e.g. the content of a
+    /// `.wasm` file, if it has one.
+    pub fn from_wasm(wasm: &[u8]) -> Result<Option<Self>, GeneratorError> {
+        let module = Module::from_buffer(wasm)
+            .map_err(|e| GeneratorError::InternalError(format!("invalid module: {e}")))?;
+        Self::from_module(&module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clarity::types::StacksEpochId;
+    use clarity::vm::costs::LimitedCostTracker;
+    use clarity::vm::database::MemoryBackingStore;
+    use clarity::vm::types::QualifiedContractIdentifier;
+    use clarity::vm::ClarityVersion;
+
+    use super::{AbiArgument, AbiFunction, AbiType, ContractAbi, ABI_VERSION};
+    use crate::compile;
+    use crate::wasm_generator::FunctionKind;
+
+    fn abi_type(clarity: &str, wasm: &[&str]) -> AbiType {
+        AbiType {
+            clarity: clarity.to_owned(),
+            wasm: wasm.iter().map(|ty| ty.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn abi_round_trip() {
+        let mut datastore = MemoryBackingStore::new();
+        let wasm = compile(
+            "(define-data-var counter uint u0)
+(define-map owners uint principal)
+(define-fungible-token gold)
+(define-non-fungible-token badge uint)
+(define-public (add (n uint) (label (buff 4)))
+  (begin
+    (var-set counter (+ (var-get counter) n))
+    (if (> n u10) (err u1) (ok (var-get counter)))))
+(define-read-only (get-counter) (var-get counter))
+(define-private (helper (flag bool)) flag)",
+            &QualifiedContractIdentifier::transient(),
+            LimitedCostTracker::new_free(),
+            ClarityVersion::latest(),
+            StacksEpochId::latest(),
+            &mut datastore.as_analysis_db(),
+        )
+        .expect("Failed to compile contract.")
+        .module
+        .emit_wasm();
+
+        let abi = ContractAbi::from_wasm(&wasm)
+            .expect("Failed to read ABI.")
+            .expect("Missing ABI.");
+        assert_eq!(abi.version, ABI_VERSION);
+        assert_eq!(abi.functions.len(), 3);
+        assert_eq!(
+            abi.function_named("add"),
+            Some(&AbiFunction {
+                name: "add".to_owned(),
+                kind: FunctionKind::Public,
+                args: vec![
+                    AbiArgument {
+                        name: "n".to_owned(),
+                        ty: abi_type("uint", &["i64", "i64"]),
+                    },
+                    AbiArgument {
+                        name: "label".to_owned(),
+                        ty: abi_type("(buff 4)", &["i32", "i32"]),
+                    },
+                ],
+                returns: abi_type("(response uint uint)", &["i32", "i64", "i64", "i64", "i64"],),
+            })
+        );
+        assert_eq!(
+            abi.function_named("get-counter").map(|f| f.kind),
+            Some(FunctionKind::ReadOnly)
+        );
+        assert_eq!(
+            abi.function_named("helper").map(|f| f.kind),
+            Some(FunctionKind::Private)
+        );
+        assert_eq!(abi.data_vars[0].name, "counter");
+        assert_eq!(abi.maps[0].key, abi_type("uint", &["i64", "i64"]));
+        assert_eq!(abi.fungible_tokens, vec!["gold".to_owned()]);
+        assert_eq!(abi.non_fungible_tokens[0].name, "badge");
+        assert!(abi.implemented_traits.is_empty());
+    }
+}
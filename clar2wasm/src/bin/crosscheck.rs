@@ -1,34 +1,224 @@
 use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
 
 use clap::Parser;
-use clar2wasm::tools::crosscheck_compare_only;
+use clar2wasm::tools::crosscheck_report;
+use clarity::vm::errors::{Error, WasmError};
+use regex::Regex;
+use serde::Serialize;
 
-/// crosscheck is a tool to compare the results of the compiled and interpreted
-/// versions of a Clarity snippet.
+/// crosscheck is a tool to compare the results, datastore changes and events
+/// of the compiled and interpreted versions of Clarity contracts.
 #[derive(Parser)]
 #[command(name = "crosscheck", version = env!("CARGO_PKG_VERSION"))]
 struct Args {
-    /// Clarity source file to compile
-    input: String,
+    /// Clarity source files, directories containing them, or glob patterns
+    /// like `contracts/**/*.clar`
+    #[arg(required = true)]
+    inputs: Vec<String>,
+    /// Write a JSON report to this file
+    #[arg(long)]
+    json: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Status {
+    Pass,
+    Diverge,
+    CompileError,
+}
+
+impl Status {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Status::Pass => "pass",
+            Status::Diverge => "diverge",
+            Status::CompileError => "compile-error",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ContractReport {
+    path: String,
+    status: Status,
+    /// The divergences, or the compilation error.
+    details: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Summary {
+    passed: usize,
+    diverged: usize,
+    compile_errors: usize,
+    contracts: Vec<ContractReport>,
+}
+
+/// Translate a glob pattern to a regex matching whole paths. `**` matches
+/// any number of directories, `*` and `?` do not match a `/`.
+fn glob_to_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    let mut regex = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                // `**/` also matches no directory at all
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex)
+}
+
+/// Collect the files under `dir`, recursively.
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            walk(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// The `.clar` files designated by `input`, a file, a directory or a glob
+/// pattern.
+fn collect_contracts(input: &str) -> std::io::Result<Vec<PathBuf>> {
+    let is_clarity = |path: &PathBuf| path.extension().map_or(false, |ext| ext == "clar");
+
+    let mut files = Vec::new();
+    if input.contains(['*', '?']) {
+        // Walk from the longest directory prefix without wildcards
+        let wildcard = input.find(['*', '?']).unwrap_or(input.len());
+        let root = match input[..wildcard].rfind('/') {
+            Some(slash) => &input[..=slash],
+            None => ".",
+        };
+        let regex = glob_to_regex(input.trim_start_matches("./"))
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidInput, error))?;
+        walk(Path::new(root), &mut files)?;
+        files.retain(|path| {
+            let path = path.to_string_lossy();
+            regex.is_match(path.trim_start_matches("./"))
+        });
+    } else if Path::new(input).is_dir() {
+        walk(Path::new(input), &mut files)?;
+    } else {
+        files.push(PathBuf::from(input));
+    }
+
+    files.retain(is_clarity);
+    files.sort();
+    Ok(files)
+}
+
+fn crosscheck_file(path: &Path) -> ContractReport {
+    let (status, details) = match fs::read_to_string(path) {
+        Ok(source) => match panic::catch_unwind(AssertUnwindSafe(|| crosscheck_report(&source))) {
+            Ok(report) => match &report.compiled.result {
+                Err(Error::Wasm(WasmError::WasmGeneratorError(error))) => {
+                    (Status::CompileError, vec![error.clone()])
+                }
+                _ if report.is_ok() => (Status::Pass, Vec::new()),
+                _ => (
+                    Status::Diverge,
+                    report
+                        .divergences
+                        .iter()
+                        .map(|divergence| divergence.to_string())
+                        .collect(),
+                ),
+            },
+            Err(_) => (Status::Diverge, vec!["crosscheck panicked".to_string()]),
+        },
+        Err(error) => (
+            Status::CompileError,
+            vec![format!("Error reading file: {}", error)],
+        ),
+    };
+
+    ContractReport {
+        path: path.display().to_string(),
+        status,
+        details,
+    }
 }
 
 fn main() {
     let args = Args::parse();
 
-    // Require a .clar extension
-    if !args.input.ends_with(".clar") {
-        eprintln!("Input file must have a .clar extension");
-        std::process::exit(1);
+    let mut paths = Vec::new();
+    for input in &args.inputs {
+        match collect_contracts(input) {
+            Ok(found) if found.is_empty() => {
+                eprintln!("No .clar files found for {}", input);
+                std::process::exit(1);
+            }
+            Ok(found) => paths.extend(found),
+            Err(error) => {
+                eprintln!("Error reading {}: {}", input, error);
+                std::process::exit(1);
+            }
+        }
     }
 
-    // Read the file.
-    let source = match fs::read_to_string(args.input.as_str()) {
-        Ok(source) => source,
-        Err(error) => {
-            eprintln!("Error reading file: {}", error);
+    let contracts: Vec<_> = paths.iter().map(|path| crosscheck_file(path)).collect();
+    let count = |status| {
+        contracts
+            .iter()
+            .filter(|contract| contract.status == status)
+            .count()
+    };
+    let summary = Summary {
+        passed: count(Status::Pass),
+        diverged: count(Status::Diverge),
+        compile_errors: count(Status::CompileError),
+        contracts,
+    };
+
+    println!("{:<14} CONTRACT", "STATUS");
+    for contract in &summary.contracts {
+        println!("{:<14} {}", contract.status.as_str(), contract.path);
+        for detail in &contract.details {
+            for line in detail.lines() {
+                println!("{:<14}   {}", "", line);
+            }
+        }
+    }
+    println!(
+        "\n{} passed, {} diverged, {} compile errors",
+        summary.passed, summary.diverged, summary.compile_errors
+    );
+
+    if let Some(json) = &args.json {
+        let report = match serde_json::to_string_pretty(&summary) {
+            Ok(report) => report,
+            Err(error) => {
+                eprintln!("Error serializing the report: {}", error);
+                std::process::exit(1);
+            }
+        };
+        if let Err(error) = fs::write(json, report) {
+            eprintln!("Error writing {}: {}", json, error);
             std::process::exit(1);
         }
-    };
+    }
 
-    crosscheck_compare_only(&source);
+    if summary.diverged > 0 {
+        std::process::exit(1);
+    }
 }
@@ -1,59 +1,716 @@
+#[cfg(feature = "developer-mode")]
+use std::collections::BTreeMap;
 use std::fs;
+use std::path::Path;
 
-use clap::Parser;
-use clar2wasm::CompileError;
+use clap::error::ErrorKind;
+#[cfg(feature = "developer-mode")]
+use clap::Subcommand;
+use clap::{CommandFactory, Parser, ValueEnum};
+use clar2wasm::project::{Project, ProjectError};
+#[cfg(feature = "developer-mode")]
+use clar2wasm::tools::{interpret_at, TestEnvironment};
+use clar2wasm::{CompileError, CompileOptions, Module, Network, TargetNetwork};
 use clarity::types::StacksEpochId;
 use clarity::vm::costs::LimitedCostTracker;
 use clarity::vm::database::MemoryBackingStore;
-use clarity::vm::types::QualifiedContractIdentifier;
-use clarity::vm::ClarityVersion;
+use clarity::vm::diagnostic::{Diagnostic, Level};
+#[cfg(feature = "developer-mode")]
+use clarity::vm::events::{FTEventType, NFTEventType, STXEventType, StacksTransactionEvent};
+#[cfg(feature = "developer-mode")]
+use clarity::vm::types::AssetIdentifier;
+use clarity::vm::types::{PrincipalData, QualifiedContractIdentifier, StandardPrincipalData};
+use clarity::vm::{ClarityVersion, SymbolicExpression};
+#[cfg(feature = "developer-mode")]
+use clarity::vm::{ContractName, Value};
+use serde_json::json;
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ClarityVersionArg {
+    Clarity1,
+    Clarity2,
+}
+
+impl From<ClarityVersionArg> for ClarityVersion {
+    fn from(version: ClarityVersionArg) -> Self {
+        match version {
+            ClarityVersionArg::Clarity1 => ClarityVersion::Clarity1,
+            ClarityVersionArg::Clarity2 => ClarityVersion::Clarity2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum EpochArg {
+    #[value(name = "2.0")]
+    Epoch20,
+    #[value(name = "2.05")]
+    Epoch2_05,
+    #[value(name = "2.1")]
+    Epoch21,
+    #[value(name = "2.2")]
+    Epoch22,
+    #[value(name = "2.3")]
+    Epoch23,
+    #[value(name = "2.4")]
+    Epoch24,
+    #[value(name = "latest")]
+    Latest,
+}
+
+impl From<EpochArg> for StacksEpochId {
+    fn from(epoch: EpochArg) -> Self {
+        match epoch {
+            EpochArg::Epoch20 => StacksEpochId::Epoch20,
+            EpochArg::Epoch2_05 => StacksEpochId::Epoch2_05,
+            EpochArg::Epoch21 => StacksEpochId::Epoch21,
+            EpochArg::Epoch22 => StacksEpochId::Epoch22,
+            EpochArg::Epoch23 => StacksEpochId::Epoch23,
+            EpochArg::Epoch24 => StacksEpochId::Epoch24,
+            EpochArg::Latest => StacksEpochId::latest(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum NetworkArg {
+    Mainnet,
+    Testnet,
+    Regtest,
+}
+
+impl From<NetworkArg> for Network {
+    fn from(network: NetworkArg) -> Self {
+        match network {
+            NetworkArg::Mainnet => Network::Mainnet,
+            NetworkArg::Testnet => Network::Testnet,
+            NetworkArg::Regtest => Network::Regtest,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Emit {
+    /// Write the WebAssembly binary to the output file
+    Wasm,
+    /// Print the WebAssembly text format, annotated with the Clarity source
+    Wat,
+    /// Both write the binary and print the text format
+    Both,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum MessageFormat {
+    /// Human readable diagnostics
+    Human,
+    /// One JSON object per diagnostic, on its own line
+    Json,
+}
+
+fn parse_deployer(deployer: &str) -> Result<StandardPrincipalData, String> {
+    match PrincipalData::parse(deployer) {
+        Ok(PrincipalData::Standard(principal)) => Ok(principal),
+        _ => Err(format!("expected a standard principal, got '{deployer}'")),
+    }
+}
+
+#[cfg(feature = "developer-mode")]
+fn parse_principal(principal: &str) -> Result<PrincipalData, String> {
+    PrincipalData::parse(principal).map_err(|_| format!("expected a principal, got '{principal}'"))
+}
+
+fn parse_contract_id(contract_id: &str) -> Result<QualifiedContractIdentifier, String> {
+    QualifiedContractIdentifier::parse(contract_id)
+        .map_err(|_| format!("expected <principal>.<contract-name>, got '{contract_id}'"))
+}
 
 /// clar2wasm is a compiler for generating WebAssembly from Clarity.
 #[derive(Parser)]
-#[command(name = "clar2wasm", version = env!("CARGO_PKG_VERSION"))]
+#[command(
+    name = "clar2wasm",
+    version = env!("CARGO_PKG_VERSION"),
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
-    /// Clarity source file to compile
-    input: String,
-    /// Output file to write compiled WebAssembly to
+    #[cfg(feature = "developer-mode")]
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Clarity source file to compile, or a directory of Clarity source
+    /// files or a Clarinet manifest to compile a project of contracts
+    #[arg(required = true)]
+    input: Option<String>,
+    /// Output file to write compiled WebAssembly to, or output directory
+    /// when compiling a project
     #[arg(short, long)]
     output: Option<String>,
+    /// Kind of output to emit
+    #[arg(long, value_enum, default_value_t = Emit::Wasm)]
+    emit: Emit,
+    /// Clarity version used to parse and analyze the contract
+    #[arg(long, value_enum, default_value_t = ClarityVersionArg::Clarity2)]
+    clarity_version: ClarityVersionArg,
+    /// Stacks epoch in which the contract will be deployed
+    #[arg(long, value_enum, default_value_t = EpochArg::Latest)]
+    epoch: EpochArg,
+    /// Identifier of the deployed contract, as <principal>.<contract-name>
+    /// (not allowed when compiling a project)
+    #[arg(long, value_parser = parse_contract_id, conflicts_with = "deployer")]
+    contract_id: Option<QualifiedContractIdentifier>,
+    /// Principal deploying the contracts of a project
+    #[arg(long, value_parser = parse_deployer)]
+    deployer: Option<StandardPrincipalData>,
+    /// Format of the diagnostics reported on compilation failures
+    #[arg(long, value_enum, default_value_t = MessageFormat::Human)]
+    message_format: MessageFormat,
+    /// Keep the standard library functions which are not used by the
+    /// contract, e.g. to call them directly while debugging
+    #[arg(long)]
+    keep_stdlib: bool,
+    /// Add a `clarity-source-map` custom section, mapping the code of the
+    /// contract functions to their Clarity source, to locate traps
+    #[arg(long)]
+    source_map: bool,
+    /// Network targeted by the contract, to compile `is-in-mainnet`,
+    /// `is-in-regtest` and `chain-id` as constants instead of querying the
+    /// host
+    #[arg(long, value_enum)]
+    network: Option<NetworkArg>,
+    /// Chain id of the targeted network, by default the one of the mainnet
+    /// or the testnet
+    #[arg(long, requires = "network")]
+    chain_id: Option<u32>,
+    /// Maximum number of 64 KiB pages the memory of the contract can grow
+    /// to, by default the limit of the host
+    #[arg(long)]
+    max_memory_pages: Option<u32>,
+}
+
+#[cfg(feature = "developer-mode")]
+#[derive(Subcommand)]
+enum Command {
+    /// Deploy contracts into an in-memory chain, then call one of their
+    /// functions and print its result, events and balance changes
+    Run(RunArgs),
+}
+
+#[cfg(feature = "developer-mode")]
+#[derive(clap::Args)]
+struct RunArgs {
+    /// Clarity source files of the contracts to deploy, in order. Each
+    /// contract is named after its file.
+    #[arg(required = true)]
+    contracts: Vec<String>,
+    /// Name of the contract containing the function, by default the last
+    /// deployed contract
+    #[arg(long)]
+    contract: Option<String>,
+    /// Name of the function to call
+    #[arg(long)]
+    function: String,
+    /// Argument of the function, as a Clarity literal. Repeat for each
+    /// argument.
+    #[arg(long = "arg")]
+    args: Vec<String>,
+    /// Principal calling the function, by default the funded test account
+    #[arg(long, value_parser = parse_principal)]
+    sender: Option<PrincipalData>,
+    /// Principal sponsoring the call
+    #[arg(long, value_parser = parse_principal)]
+    sponsor: Option<PrincipalData>,
+    /// Principal deploying the contracts
+    #[arg(long, value_parser = parse_deployer)]
+    deployer: Option<StandardPrincipalData>,
+    /// Clarity version used to parse and analyze the contracts
+    #[arg(long, value_enum, default_value_t = ClarityVersionArg::Clarity2)]
+    clarity_version: ClarityVersionArg,
+    /// Stacks epoch in which the contracts are deployed
+    #[arg(long, value_enum, default_value_t = EpochArg::Latest)]
+    epoch: EpochArg,
+}
+
+impl Args {
+    fn compile_options(&self) -> CompileOptions {
+        CompileOptions {
+            keep_unused_stdlib: self.keep_stdlib,
+            source_map: self.source_map,
+            network: self.network.map(|network| {
+                let mut network = TargetNetwork::new(network.into());
+                if let Some(chain_id) = self.chain_id {
+                    network.chain_id = chain_id;
+                }
+                network
+            }),
+            max_memory_pages: self.max_memory_pages,
+            ..CompileOptions::default()
+        }
+    }
+}
+
+/// Serialize a diagnostic for `file` as a JSON object.
+fn diagnostic_to_json(file: &str, diagnostic: &Diagnostic) -> serde_json::Value {
+    let level = match diagnostic.level {
+        Level::Note => "note",
+        Level::Warning => "warning",
+        Level::Error => "error",
+    };
+    let spans: Vec<_> = diagnostic
+        .spans
+        .iter()
+        .map(|span| {
+            json!({
+                "start_line": span.start_line,
+                "start_column": span.start_column,
+                "end_line": span.end_line,
+                "end_column": span.end_column,
+            })
+        })
+        .collect();
+    json!({
+        "file": file,
+        "level": level,
+        "message": diagnostic.message,
+        "spans": spans,
+        "suggestion": diagnostic.suggestion,
+    })
+}
+
+/// Report the diagnostics of `file`, printing them to stderr in the human
+/// format, or to stdout in the JSON format.
+fn report_diagnostics(file: &str, diagnostics: &[Diagnostic], format: MessageFormat) {
+    for diagnostic in diagnostics.iter() {
+        match format {
+            MessageFormat::Human => eprintln!("{diagnostic}"),
+            MessageFormat::Json => println!("{}", diagnostic_to_json(file, diagnostic)),
+        }
+    }
+}
+
+/// Report an error of `file` which has no location in a contract, like an
+/// IO error, then exit.
+fn fail(file: &str, message: &str, format: MessageFormat) -> ! {
+    match format {
+        MessageFormat::Human => eprintln!("{message}"),
+        MessageFormat::Json => {
+            let diagnostic = Diagnostic {
+                level: Level::Error,
+                message: message.to_string(),
+                spans: vec![],
+                suggestion: None,
+            };
+            println!("{}", diagnostic_to_json(file, &diagnostic));
+        }
+    }
+    std::process::exit(1);
+}
+
+/// Print and/or write the compiled module, depending on `emit`.
+fn emit_module(
+    module: &mut Module,
+    expressions: &[SymbolicExpression],
+    output: &str,
+    emit: Emit,
+    format: MessageFormat,
+) {
+    if emit != Emit::Wasm {
+        match clar2wasm::listing::annotated_wat(module, expressions) {
+            Ok(wat) => print!("{wat}"),
+            Err(error) => fail(
+                output,
+                &format!("Error printing WebAssembly text format: {:?}", error),
+                format,
+            ),
+        }
+    }
+
+    if emit == Emit::Wat {
+        return;
+    }
+
+    if let Err(error) = module.emit_wasm_file(output) {
+        fail(
+            output,
+            &format!("Error writing Wasm file, {}: {}", output, error),
+            format,
+        );
+    }
+}
+
+/// Compile every contract of a project, given as a directory or a manifest,
+/// writing one Wasm file per contract.
+fn compile_project(
+    args: &Args,
+    input: &str,
+    clarity_version: ClarityVersion,
+    epoch: StacksEpochId,
+) {
+    let input = Path::new(input);
+    let deployer = args
+        .deployer
+        .clone()
+        .unwrap_or_else(StandardPrincipalData::transient);
+
+    let project = if input.is_dir() {
+        Project::from_directory(input, deployer)
+    } else {
+        Project::from_manifest(input, deployer)
+    }
+    .unwrap_or_else(|error| {
+        fail(
+            &input.to_string_lossy(),
+            &error.to_string(),
+            args.message_format,
+        )
+    });
+
+    let mut datastore = MemoryBackingStore::new();
+    let results = project
+        .compile(
+            clarity_version,
+            epoch,
+            &mut datastore.as_analysis_db(),
+            &args.compile_options(),
+        )
+        .unwrap_or_else(|error| {
+            if args.message_format == MessageFormat::Human {
+                eprintln!("{error}");
+            }
+            let contract_path = |contract_id: &QualifiedContractIdentifier| {
+                project
+                    .contracts()
+                    .iter()
+                    .find(|contract| &contract.contract_id == contract_id)
+                    .map(|contract| contract.path.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            };
+            match &error {
+                ProjectError::Parse(contract_id, diagnostics) => report_diagnostics(
+                    &contract_path(contract_id),
+                    diagnostics,
+                    args.message_format,
+                ),
+                ProjectError::Compile(contract_id, compile_error) => match compile_error.as_ref() {
+                    CompileError::Generic { diagnostics, .. } => report_diagnostics(
+                        &contract_path(contract_id),
+                        diagnostics,
+                        args.message_format,
+                    ),
+                },
+                _ => fail(
+                    &input.to_string_lossy(),
+                    &error.to_string(),
+                    args.message_format,
+                ),
+            }
+            std::process::exit(1);
+        });
+
+    if let Some(output_dir) = &args.output {
+        if let Err(error) = fs::create_dir_all(output_dir) {
+            fail(
+                output_dir,
+                &format!("Error creating output directory, {}: {}", output_dir, error),
+                args.message_format,
+            );
+        }
+    }
+
+    for (contract, mut result) in results {
+        // Write next to the source, or in the output directory, using the
+        // contract name with a .wasm extension
+        let output = match &args.output {
+            Some(output_dir) => {
+                Path::new(output_dir).join(format!("{}.wasm", contract.contract_id.name))
+            }
+            None => contract.path.with_extension("wasm"),
+        };
+        emit_module(
+            &mut result.module,
+            &result.ast.expressions,
+            &output.to_string_lossy(),
+            args.emit,
+            args.message_format,
+        );
+    }
+}
+
+/// Describe an event emitted by a contract call.
+#[cfg(feature = "developer-mode")]
+fn describe_event(event: &StacksTransactionEvent) -> String {
+    let asset =
+        |asset: &AssetIdentifier| format!("{}::{}", asset.contract_identifier, asset.asset_name);
+    match event {
+        StacksTransactionEvent::SmartContractEvent(event) => {
+            format!("print {}: {}", event.key.0, event.value)
+        }
+        StacksTransactionEvent::STXEvent(STXEventType::STXTransferEvent(event)) => format!(
+            "stx-transfer {} from {} to {}",
+            event.amount, event.sender, event.recipient
+        ),
+        StacksTransactionEvent::STXEvent(STXEventType::STXMintEvent(event)) => {
+            format!("stx-mint {} to {}", event.amount, event.recipient)
+        }
+        StacksTransactionEvent::STXEvent(STXEventType::STXBurnEvent(event)) => {
+            format!("stx-burn {} from {}", event.amount, event.sender)
+        }
+        StacksTransactionEvent::FTEvent(FTEventType::FTTransferEvent(event)) => format!(
+            "ft-transfer {} {} from {} to {}",
+            event.amount,
+            asset(&event.asset_identifier),
+            event.sender,
+            event.recipient
+        ),
+        StacksTransactionEvent::FTEvent(FTEventType::FTMintEvent(event)) => format!(
+            "ft-mint {} {} to {}",
+            event.amount,
+            asset(&event.asset_identifier),
+            event.recipient
+        ),
+        StacksTransactionEvent::FTEvent(FTEventType::FTBurnEvent(event)) => format!(
+            "ft-burn {} {} from {}",
+            event.amount,
+            asset(&event.asset_identifier),
+            event.sender
+        ),
+        StacksTransactionEvent::NFTEvent(NFTEventType::NFTTransferEvent(event)) => format!(
+            "nft-transfer {} {} from {} to {}",
+            asset(&event.asset_identifier),
+            event.value,
+            event.sender,
+            event.recipient
+        ),
+        StacksTransactionEvent::NFTEvent(NFTEventType::NFTMintEvent(event)) => format!(
+            "nft-mint {} {} to {}",
+            asset(&event.asset_identifier),
+            event.value,
+            event.recipient
+        ),
+        StacksTransactionEvent::NFTEvent(NFTEventType::NFTBurnEvent(event)) => format!(
+            "nft-burn {} {} from {}",
+            asset(&event.asset_identifier),
+            event.value,
+            event.sender
+        ),
+        event => format!("{event:?}"),
+    }
+}
+
+/// Sum the balance changes of each principal and asset in `events`. Non
+/// fungible tokens are counted.
+#[cfg(feature = "developer-mode")]
+fn balance_changes(events: &[StacksTransactionEvent]) -> BTreeMap<(String, String), i128> {
+    let mut changes = BTreeMap::new();
+    let mut change = |principal: &PrincipalData, asset: String, amount: i128| {
+        *changes.entry((principal.to_string(), asset)).or_insert(0) += amount;
+    };
+    let stx = || "STX".to_owned();
+    let asset =
+        |asset: &AssetIdentifier| format!("{}::{}", asset.contract_identifier, asset.asset_name);
+    for event in events {
+        match event {
+            StacksTransactionEvent::STXEvent(STXEventType::STXTransferEvent(event)) => {
+                change(&event.sender, stx(), -(event.amount as i128));
+                change(&event.recipient, stx(), event.amount as i128);
+            }
+            StacksTransactionEvent::STXEvent(STXEventType::STXMintEvent(event)) => {
+                change(&event.recipient, stx(), event.amount as i128);
+            }
+            StacksTransactionEvent::STXEvent(STXEventType::STXBurnEvent(event)) => {
+                change(&event.sender, stx(), -(event.amount as i128));
+            }
+            StacksTransactionEvent::FTEvent(FTEventType::FTTransferEvent(event)) => {
+                let name = asset(&event.asset_identifier);
+                change(&event.sender, name.clone(), -(event.amount as i128));
+                change(&event.recipient, name, event.amount as i128);
+            }
+            StacksTransactionEvent::FTEvent(FTEventType::FTMintEvent(event)) => {
+                change(
+                    &event.recipient,
+                    asset(&event.asset_identifier),
+                    event.amount as i128,
+                );
+            }
+            StacksTransactionEvent::FTEvent(FTEventType::FTBurnEvent(event)) => {
+                change(
+                    &event.sender,
+                    asset(&event.asset_identifier),
+                    -(event.amount as i128),
+                );
+            }
+            StacksTransactionEvent::NFTEvent(NFTEventType::NFTTransferEvent(event)) => {
+                let name = asset(&event.asset_identifier);
+                change(&event.sender, name.clone(), -1);
+                change(&event.recipient, name, 1);
+            }
+            StacksTransactionEvent::NFTEvent(NFTEventType::NFTMintEvent(event)) => {
+                change(&event.recipient, asset(&event.asset_identifier), 1);
+            }
+            StacksTransactionEvent::NFTEvent(NFTEventType::NFTBurnEvent(event)) => {
+                change(&event.sender, asset(&event.asset_identifier), -1);
+            }
+            _ => {}
+        }
+    }
+    changes
+}
+
+/// Deploy the contracts in an in-memory chain, then call the function and
+/// print its result, events and balance changes.
+#[cfg(feature = "developer-mode")]
+fn run(args: &RunArgs) {
+    let clarity_version = ClarityVersion::from(args.clarity_version);
+    let epoch = StacksEpochId::from(args.epoch);
+    let deployer = args
+        .deployer
+        .clone()
+        .unwrap_or_else(StandardPrincipalData::transient);
+
+    let mut env = TestEnvironment::new(epoch, clarity_version);
+
+    let mut contract_name = None;
+    for path in &args.contracts {
+        let source = fs::read_to_string(path).unwrap_or_else(|error| {
+            eprintln!("Error reading file, {path}: {error}");
+            std::process::exit(1);
+        });
+        let name = Path::new(path)
+            .file_stem()
+            .and_then(|stem| ContractName::try_from(stem.to_string_lossy().into_owned()).ok())
+            .unwrap_or_else(|| {
+                eprintln!("Invalid contract name for {path}");
+                std::process::exit(1);
+            });
+        let contract_id = QualifiedContractIdentifier::new(deployer.clone(), name.clone());
+        if let Err(error) = env.init_contract_with_id(contract_id, &source) {
+            eprintln!("Error deploying {path}: {error}");
+            std::process::exit(1);
+        }
+        contract_name = Some(name.to_string());
+    }
+
+    let contract_name = args.contract.clone().or(contract_name).unwrap_or_default();
+
+    let function_args: Vec<Value> = args
+        .args
+        .iter()
+        .map(|arg| match interpret_at(arg, epoch, clarity_version) {
+            Ok(Some(value)) => value,
+            _ => {
+                eprintln!("Invalid argument, expected a Clarity literal: {arg}");
+                std::process::exit(1);
+            }
+        })
+        .collect();
+
+    let sender = args
+        .sender
+        .clone()
+        .unwrap_or_else(|| StandardPrincipalData::transient().into());
+
+    let result = env
+        .call_contract_function(
+            &contract_name,
+            &args.function,
+            &function_args,
+            sender,
+            args.sponsor.clone(),
+        )
+        .unwrap_or_else(|error| {
+            eprintln!("Error calling {contract_name}.{}: {error}", args.function);
+            std::process::exit(1);
+        });
+
+    println!("result: {}", result.value);
+    println!("committed: {}", result.committed);
+    println!("events:");
+    for event in &result.events {
+        println!("  {}", describe_event(event));
+    }
+    println!("balance changes:");
+    for ((principal, asset), change) in balance_changes(&result.events) {
+        if change != 0 {
+            println!("  {principal}: {change:+} {asset}");
+        }
+    }
 }
 
 fn main() {
     let args = Args::parse();
 
+    #[cfg(feature = "developer-mode")]
+    if let Some(Command::Run(run_args)) = &args.command {
+        run(run_args);
+        return;
+    }
+
+    // Required when no subcommand is given.
+    let input = args.input.clone().unwrap_or_default();
+
+    let clarity_version = ClarityVersion::from(args.clarity_version);
+    let epoch = StacksEpochId::from(args.epoch);
+
+    if clarity_version > ClarityVersion::default_for_epoch(epoch) {
+        fail(
+            &input,
+            &format!("{clarity_version} is not supported in epoch {epoch}"),
+            args.message_format,
+        );
+    }
+
+    if Path::new(&input).is_dir() || input.ends_with(".toml") {
+        // Project mode is only known once the input is inspected, so clap
+        // cannot express this conflict declaratively.
+        if args.contract_id.is_some() {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "the argument '--contract-id' cannot be used when compiling a project; \
+                     use '--deployer' instead",
+                )
+                .exit();
+        }
+        compile_project(&args, &input, clarity_version, epoch);
+        return;
+    }
+
     // Require a .clar extension
-    if !args.input.ends_with(".clar") {
-        eprintln!("Input file must have a .clar extension");
-        std::process::exit(1);
+    if !input.ends_with(".clar") {
+        fail(
+            &input,
+            "Input file must have a .clar extension",
+            args.message_format,
+        );
     }
 
     // Read the file.
-    let source = match fs::read_to_string(args.input.as_str()) {
+    let source = match fs::read_to_string(input.as_str()) {
         Ok(source) => source,
-        Err(error) => {
-            eprintln!("Error reading file: {}", error);
-            std::process::exit(1);
-        }
+        Err(error) => fail(
+            &input,
+            &format!("Error reading file: {}", error),
+            args.message_format,
+        ),
     };
 
     // Define some settings
-    let contract_id = QualifiedContractIdentifier::transient();
-    let clarity_version = ClarityVersion::Clarity2;
-    let epoch = StacksEpochId::latest();
+    let contract_id = args
+        .contract_id
+        .clone()
+        .unwrap_or_else(QualifiedContractIdentifier::transient);
 
     // Setup a datastore and cost tracker
     let mut datastore = MemoryBackingStore::new();
     let cost_track = LimitedCostTracker::new_free();
 
     // Pass the source code to the compiler.
-    let result = clar2wasm::compile(
+    let mut result = clar2wasm::compile_with_options(
         &source,
         &contract_id,
         cost_track,
         clarity_version,
         epoch,
         &mut datastore.as_analysis_db(),
+        &args.compile_options(),
     )
     .unwrap_or_else(|err| match err {
         CompileError::Generic {
@@ -61,27 +718,26 @@ fn main() {
             ast: _,
             cost_tracker: _,
         } => {
-            for diagnostic in diagnostics.iter() {
-                eprintln!("{diagnostic}");
-            }
+            report_diagnostics(&input, &diagnostics, args.message_format);
             std::process::exit(1);
         }
     });
 
-    let mut module = result.module;
-
     // Write the compiled WebAssembly to a file.
-    let output = args.output.unwrap_or_else(|| {
+    let output = args.output.clone().unwrap_or_else(|| {
         // Use the input file name with a .wasm extension
-        let mut output = args.input.clone();
+        let mut output = input.clone();
 
         // Strip the .clar and add .wasm
         output.replace_range(output.len() - 4.., "wasm");
         output
     });
 
-    if let Err(error) = module.emit_wasm_file(output.as_str()) {
-        eprintln!("Error writing Wasm file, {}: {}", output, error);
-        std::process::exit(1);
-    }
+    emit_module(
+        &mut result.module,
+        &result.ast.expressions,
+        &output,
+        args.emit,
+        args.message_format,
+    );
 }
@@ -0,0 +1,279 @@
+//! The `constant_folding` module evaluates, at compile time, the expressions
+//! which only apply pure native functions to literals, like `(+ u1 u2)` or
+//! `(sha256 0x00)`, so that the generator can emit their result as a
+//! constant instead of calls into the standard library.
+//!
+//! The expressions are evaluated with the interpreter, so the folded values
+//! are the ones the interpreter would compute. An expression whose evaluation
+//! fails, e.g. because of an overflow, is not folded, so that it still raises
+//! the same error at runtime.
+
+use std::collections::{HashMap, HashSet};
+
+use clarity::consts::CHAIN_ID_TESTNET;
+use clarity::types::StacksEpochId;
+use clarity::vm::contexts::GlobalContext;
+use clarity::vm::costs::LimitedCostTracker;
+use clarity::vm::database::MemoryBackingStore;
+use clarity::vm::types::{QualifiedContractIdentifier, SequenceData};
+use clarity::vm::{
+    eval_all, ClarityVersion, ContractContext, SymbolicExpression, SymbolicExpressionType, Value,
+};
+
+/// Native functions which have no side effect and only depend on their
+/// arguments.
+const PURE_FUNCTIONS: &[&str] = &[
+    "+",
+    "-",
+    "*",
+    "/",
+    "mod",
+    "pow",
+    "sqrti",
+    "log2",
+    "<",
+    ">",
+    "<=",
+    ">=",
+    "is-eq",
+    "not",
+    "and",
+    "or",
+    "xor",
+    "bit-and",
+    "bit-or",
+    "bit-xor",
+    "bit-not",
+    "bit-shift-left",
+    "bit-shift-right",
+    "to-int",
+    "to-uint",
+    "len",
+    "concat",
+    "sha256",
+    "sha512",
+    "sha512/256",
+    "keccak256",
+    "hash160",
+    "int-to-ascii",
+    "int-to-utf8",
+    "buff-to-int-be",
+    "buff-to-int-le",
+    "buff-to-uint-be",
+    "buff-to-uint-le",
+];
+
+/// Collects the IDs of the foldable expressions in `expr`, i.e. the literals
+/// and the applications of pure native functions to foldable expressions.
+/// Returns true if `expr` itself is foldable.
+fn collect_foldable(expr: &SymbolicExpression, foldable: &mut HashSet<u64>) -> bool {
+    let is_foldable = match &expr.expr {
+        SymbolicExpressionType::LiteralValue(_) => true,
+        SymbolicExpressionType::List(list) => match list.split_first() {
+            Some((function, args)) => {
+                // Every argument is visited, to collect its own sub-expressions
+                let args_foldable = args
+                    .iter()
+                    .fold(true, |all, arg| collect_foldable(arg, foldable) && all);
+                function
+                    .match_atom()
+                    .map_or(false, |name| PURE_FUNCTIONS.contains(&name.as_str()))
+                    && !args.is_empty()
+                    && args_foldable
+            }
+            None => false,
+        },
+        _ => false,
+    };
+    if is_foldable {
+        foldable.insert(expr.id);
+    }
+    is_foldable
+}
+
+/// Returns true if `value` can be emitted as a constant, either as an
+/// immediate or in the literal memory.
+fn is_constant(value: &Value) -> bool {
+    matches!(
+        value,
+        Value::Int(_)
+            | Value::UInt(_)
+            | Value::Bool(_)
+            | Value::Sequence(SequenceData::Buffer(_))
+            | Value::Sequence(SequenceData::String(_))
+    )
+}
+
+/// Evaluates the foldable expressions, outermost first, with a single
+/// datastore.
+struct Folder<'a> {
+    foldable: &'a HashSet<u64>,
+    datastore: Option<MemoryBackingStore>,
+    clarity_version: ClarityVersion,
+    epoch: StacksEpochId,
+    folded: HashMap<u64, Value>,
+}
+
+impl Folder<'_> {
+    /// Fold the outermost foldable applications in `expr`. When the
+    /// evaluation of an application fails, its arguments are folded on their
+    /// own.
+    fn visit(&mut self, expr: &SymbolicExpression) {
+        let SymbolicExpressionType::List(list) = &expr.expr else {
+            return;
+        };
+        if self.foldable.contains(&expr.id) {
+            if let Some(value) = self.evaluate(expr) {
+                self.folded.insert(expr.id, value);
+                return;
+            }
+        }
+        for sub_expr in list.iter() {
+            self.visit(sub_expr);
+        }
+    }
+
+    /// Evaluate `expr` with the interpreter, if it evaluates without error
+    /// to a constant.
+    fn evaluate(&mut self, expr: &SymbolicExpression) -> Option<Value> {
+        let datastore = self.datastore.get_or_insert_with(MemoryBackingStore::new);
+        let mut contract_context = ContractContext::new(
+            QualifiedContractIdentifier::transient(),
+            self.clarity_version,
+        );
+        let mut global_context = GlobalContext::new(
+            false,
+            CHAIN_ID_TESTNET,
+            datastore.as_clarity_db(),
+            LimitedCostTracker::new_free(),
+            self.epoch,
+        );
+        global_context.begin();
+
+        eval_all(
+            std::slice::from_ref(expr),
+            &mut contract_context,
+            &mut global_context,
+            None,
+        )
+        .ok()
+        .flatten()
+        .filter(is_constant)
+    }
+}
+
+/// Evaluate at compile time the outermost applications of pure native
+/// functions to literals in `expressions` which evaluate without error to a
+/// constant. Returns their values, by expression ID.
+pub(crate) fn fold_all(
+    expressions: &[SymbolicExpression],
+    clarity_version: ClarityVersion,
+    epoch: StacksEpochId,
+) -> HashMap<u64, Value> {
+    let mut foldable = HashSet::new();
+    for expr in expressions {
+        collect_foldable(expr, &mut foldable);
+    }
+
+    let mut folder = Folder {
+        foldable: &foldable,
+        datastore: None,
+        clarity_version,
+        epoch,
+        folded: HashMap::new(),
+    };
+    for expr in expressions {
+        folder.visit(expr);
+    }
+    folder.folded
+}
+
+#[cfg(test)]
+mod tests {
+    use clarity::types::StacksEpochId;
+    use clarity::vm::ast::build_ast;
+    use clarity::vm::costs::LimitedCostTracker;
+    use clarity::vm::types::QualifiedContractIdentifier;
+    use clarity::vm::{ClarityVersion, Value};
+
+    use super::fold_all;
+    use crate::tools::{crosscheck, evaluate};
+
+    fn fold_snippet(snippet: &str) -> Option<Value> {
+        let ast = build_ast(
+            &QualifiedContractIdentifier::transient(),
+            snippet,
+            &mut LimitedCostTracker::new_free(),
+            ClarityVersion::latest(),
+            StacksEpochId::latest(),
+        )
+        .unwrap();
+        fold_all(
+            &ast.expressions,
+            ClarityVersion::latest(),
+            StacksEpochId::latest(),
+        )
+        .remove(&ast.expressions[0].id)
+    }
+
+    #[test]
+    fn fold_literal_subtrees() {
+        assert_eq!(fold_snippet("(+ u1 u2)"), Some(Value::UInt(3)));
+        assert_eq!(fold_snippet("(* 10 (pow 2 8))"), Some(Value::Int(2560)));
+        assert_eq!(fold_snippet("(< 1 2)"), Some(Value::Bool(true)));
+        assert_eq!(
+            fold_snippet("(concat \"a\" \"b\")"),
+            Some(Value::string_ascii_from_bytes(b"ab".to_vec()).unwrap())
+        );
+        assert!(fold_snippet("(sha256 0x00)").is_some());
+    }
+
+    #[test]
+    fn do_not_fold_errors_or_variables() {
+        assert_eq!(
+            fold_snippet("(+ u340282366920938463463374607431768211455 u1)"),
+            None
+        );
+        assert_eq!(fold_snippet("(/ 1 0)"), None);
+        assert_eq!(fold_snippet("(+ 1 block-height)"), None);
+        assert_eq!(fold_snippet("(list 1 2)"), None);
+    }
+
+    #[test]
+    fn fold_outermost_expressions() {
+        let ast = build_ast(
+            &QualifiedContractIdentifier::transient(),
+            "(+ (* 2 (+ 1 2)) block-height)",
+            &mut LimitedCostTracker::new_free(),
+            ClarityVersion::latest(),
+            StacksEpochId::latest(),
+        )
+        .unwrap();
+        let folded = fold_all(
+            &ast.expressions,
+            ClarityVersion::latest(),
+            StacksEpochId::latest(),
+        );
+
+        let product = &ast.expressions[0].match_list().unwrap()[1];
+        assert_eq!(folded.get(&product.id), Some(&Value::Int(6)));
+        assert_eq!(folded.len(), 1);
+    }
+
+    #[test]
+    fn folded_expressions() {
+        crosscheck("(* 10 (pow 2 8))", evaluate("2560"));
+        crosscheck("(concat \"a\" \"b\")", evaluate("\"ab\""));
+        crosscheck(
+            "(sha256 0x00)",
+            evaluate("0x6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d"),
+        );
+        crosscheck("(is-eq (+ 1 2) 3)", evaluate("true"));
+    }
+
+    #[test]
+    fn folded_errors_are_runtime_errors() {
+        crosscheck("(+ u340282366920938463463374607431768211455 u1)", Err(()));
+        crosscheck("(/ 1 0)", Err(()));
+    }
+}
@@ -0,0 +1,324 @@
+//! The `coverage` module implements the instrumentation which counts how
+//! many times each Clarity expression is executed, and the LCOV report built
+//! from these counts.
+//!
+//! Each instrumented expression increments its own counter, in a region of
+//! the literal memory. The memory of a contract only lives for one call from
+//! the host, so when the outermost function returns, the counters are
+//! reported to the host with `print`, as a buffer starting with
+//! `COVERAGE_MAGIC`. The host side then sums the counters of all the calls.
+
+use std::collections::{BTreeMap, HashMap};
+
+use clarity::vm::representations::Span;
+use clarity::vm::types::SequenceData;
+use clarity::vm::{SymbolicExpression, SymbolicExpressionType, Value};
+use walrus::ir::{BinaryOp, LoadKind, MemArg, StoreKind, UnaryOp};
+use walrus::{ActiveData, DataKind, FunctionId, GlobalId, InstrSeqBuilder, MemoryId, Module};
+
+/// Prefix of the buffers printed by instrumented contracts to report their
+/// counters.
+pub const COVERAGE_MAGIC: &[u8] = b"clar2wasm-coverage";
+
+/// Size of the header of the counters region: the type prefix and length of
+/// a serialized buffer, then `COVERAGE_MAGIC`.
+const HEADER_SIZE: u32 = 5 + COVERAGE_MAGIC.len() as u32;
+
+/// A branching expression, like `if` or `match`, with the counters of the
+/// expressions of each of its branches.
+#[derive(Debug, Clone)]
+pub struct Branch {
+    pub span: Span,
+    pub counters: Vec<usize>,
+}
+
+/// The spans of the instrumented expressions, indexed by counter.
+#[derive(Debug, Clone, Default)]
+pub struct CoverageMap {
+    pub spans: Vec<Span>,
+    pub branches: Vec<Branch>,
+}
+
+/// The state of the instrumentation while the module is generated.
+pub(crate) struct Instrumentation {
+    map: CoverageMap,
+    /// Offset of the counters region.
+    region: GlobalId,
+    /// Length of the buffer reported to the host.
+    length: GlobalId,
+    /// Depth of the calls of the contract functions, to report the counters
+    /// when the outermost function returns.
+    depth: GlobalId,
+    counter_of_expr: HashMap<u64, usize>,
+    /// Branching expressions, with the IDs of the expressions of their
+    /// branches.
+    pending_branches: Vec<(Span, Vec<u64>)>,
+}
+
+impl Instrumentation {
+    pub(crate) fn new(module: &mut Module) -> Self {
+        let mut add_global = |name: &str, mutable| {
+            let global = module.globals.add_local(
+                walrus::ValType::I32,
+                mutable,
+                walrus::InitExpr::Value(walrus::ir::Value::I32(0)),
+            );
+            module.globals.get_mut(global).name = Some(name.to_string());
+            global
+        };
+        Instrumentation {
+            map: CoverageMap::default(),
+            region: add_global("coverage-region", false),
+            length: add_global("coverage-length", false),
+            depth: add_global("coverage-depth", true),
+            counter_of_expr: HashMap::new(),
+            pending_branches: Vec::new(),
+        }
+    }
+
+    /// Emit the increment of the counter of `expr`, and record the branches
+    /// of `expr` if it is a conditional.
+    pub(crate) fn count(
+        &mut self,
+        builder: &mut InstrSeqBuilder,
+        memory: MemoryId,
+        expr: &SymbolicExpression,
+    ) {
+        let span = expr.span();
+        if span.start_line == 0 {
+            return;
+        }
+
+        let counter = *self.counter_of_expr.entry(expr.id).or_insert_with(|| {
+            self.map.spans.push(span.clone());
+            self.map.spans.len() - 1
+        });
+        let memarg = MemArg {
+            align: 4,
+            offset: HEADER_SIZE + 4 * counter as u32,
+        };
+        builder
+            .global_get(self.region)
+            .global_get(self.region)
+            .load(memory, LoadKind::I32 { atomic: false }, memarg)
+            .i32_const(1)
+            .binop(BinaryOp::I32Add)
+            .store(memory, StoreKind::I32 { atomic: false }, memarg);
+
+        if let SymbolicExpressionType::List(list) = &expr.expr {
+            let branches: &[usize] = match list.first().and_then(|head| head.match_atom()) {
+                Some(name) if name.as_str() == "if" && list.len() == 4 => &[2, 3],
+                Some(name) if name.as_str() == "match" && list.len() == 5 => &[3, 4],
+                Some(name) if name.as_str() == "match" && list.len() == 6 => &[3, 5],
+                _ => &[],
+            };
+            if !branches.is_empty() {
+                self.pending_branches
+                    .push((span.clone(), branches.iter().map(|i| list[*i].id).collect()));
+            }
+        }
+    }
+
+    /// Emit the entry in a contract function.
+    pub(crate) fn enter(&self, builder: &mut InstrSeqBuilder) {
+        builder
+            .global_get(self.depth)
+            .i32_const(1)
+            .binop(BinaryOp::I32Add)
+            .global_set(self.depth);
+    }
+
+    /// Emit the exit of a contract function, which reports the counters to
+    /// the host through `print` if it is the outermost function.
+    pub(crate) fn exit(&self, builder: &mut InstrSeqBuilder, print: FunctionId) {
+        builder
+            .global_get(self.depth)
+            .i32_const(1)
+            .binop(BinaryOp::I32Sub)
+            .global_set(self.depth)
+            .global_get(self.depth)
+            .unop(UnaryOp::I32Eqz)
+            .if_else(
+                None,
+                |then| {
+                    then.global_get(self.region)
+                        .global_get(self.length)
+                        .call(print);
+                },
+                |_| {},
+            );
+    }
+
+    /// Add the counters region at `offset`, and return the coverage map and
+    /// the size of the region.
+    pub(crate) fn finish(
+        mut self,
+        module: &mut Module,
+        memory: MemoryId,
+        offset: u32,
+    ) -> (CoverageMap, u32) {
+        // The region is the serialization of a buffer, ready to be printed.
+        let data_length = COVERAGE_MAGIC.len() as u32 + 4 * self.map.spans.len() as u32;
+        let mut data = vec![0x02];
+        data.extend_from_slice(&data_length.to_be_bytes());
+        data.extend_from_slice(COVERAGE_MAGIC);
+        data.resize(5 + data_length as usize, 0);
+        let size = data.len() as u32;
+        module.data.add(
+            DataKind::Active(ActiveData {
+                memory,
+                location: walrus::ActiveDataLocation::Absolute(offset),
+            }),
+            data,
+        );
+
+        for (global, value) in [(self.region, offset), (self.length, size)] {
+            module.globals.get_mut(global).kind = walrus::GlobalKind::Local(
+                walrus::InitExpr::Value(walrus::ir::Value::I32(value as i32)),
+            );
+        }
+
+        // Branches whose expressions were not all instrumented, e.g. because
+        // they were folded, are not reported.
+        for (span, ids) in self.pending_branches {
+            let counters: Option<Vec<_>> = ids
+                .iter()
+                .map(|id| self.counter_of_expr.get(id).copied())
+                .collect();
+            if let Some(counters) = counters {
+                self.map.branches.push(Branch { span, counters });
+            }
+        }
+
+        (self.map, size)
+    }
+}
+
+/// The counters reported by an instrumented contract in `value`, or `None`
+/// if `value` is not a coverage report.
+pub fn parse_counters(value: &Value) -> Option<Vec<u32>> {
+    match value {
+        Value::Sequence(SequenceData::Buffer(buffer)) => {
+            let counters = buffer.data.strip_prefix(COVERAGE_MAGIC)?;
+            Some(
+                counters
+                    .chunks_exact(4)
+                    .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                    .collect(),
+            )
+        }
+        _ => None,
+    }
+}
+
+/// The execution counts of the expressions of a contract.
+#[derive(Debug, Clone)]
+pub struct ContractCoverage {
+    pub map: CoverageMap,
+    pub counts: Vec<u64>,
+}
+
+impl ContractCoverage {
+    pub fn new(map: CoverageMap) -> Self {
+        let counts = vec![0; map.spans.len()];
+        ContractCoverage { map, counts }
+    }
+
+    /// Add the counters reported by one call of the contract.
+    pub fn add(&mut self, counters: &[u32]) {
+        for (count, counter) in self.counts.iter_mut().zip(counters) {
+            *count += u64::from(*counter);
+        }
+    }
+
+    /// The LCOV record of the contract, whose source is at `source_path`.
+    /// The count of a line is the highest count of the expressions starting
+    /// on it.
+    pub fn lcov(&self, source_path: &str) -> String {
+        let mut lines = BTreeMap::new();
+        for (span, count) in self.map.spans.iter().zip(&self.counts) {
+            let line = lines.entry(span.start_line).or_insert(0);
+            *line = (*line).max(*count);
+        }
+
+        let mut lcov = format!("TN:\nSF:{source_path}\n");
+        for (i, branch) in self.map.branches.iter().enumerate() {
+            for (j, counter) in branch.counters.iter().enumerate() {
+                let taken = self.counts[*counter];
+                let taken = if lines.get(&branch.span.start_line) == Some(&0) {
+                    "-".to_string()
+                } else {
+                    taken.to_string()
+                };
+                lcov.push_str(&format!(
+                    "BRDA:{},{},{},{}\n",
+                    branch.span.start_line, i, j, taken
+                ));
+            }
+        }
+        let branches = self.map.branches.iter().flat_map(|branch| &branch.counters);
+        lcov.push_str(&format!(
+            "BRF:{}\nBRH:{}\n",
+            branches.clone().count(),
+            branches
+                .filter(|counter| self.counts[**counter] > 0)
+                .count()
+        ));
+        for (line, count) in &lines {
+            lcov.push_str(&format!("DA:{line},{count}\n"));
+        }
+        lcov.push_str(&format!(
+            "LF:{}\nLH:{}\nend_of_record\n",
+            lines.len(),
+            lines.values().filter(|count| **count > 0).count()
+        ));
+        lcov
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clarity::vm::representations::Span;
+    use clarity::vm::Value;
+
+    use super::{parse_counters, Branch, ContractCoverage, CoverageMap, COVERAGE_MAGIC};
+
+    fn span(line: u32) -> Span {
+        Span {
+            start_line: line,
+            start_column: 1,
+            end_line: line,
+            end_column: 10,
+        }
+    }
+
+    #[test]
+    fn parse_coverage_report() {
+        let mut data = COVERAGE_MAGIC.to_vec();
+        data.extend_from_slice(&[1, 0, 0, 0, 2, 1, 0, 0]);
+        assert_eq!(
+            parse_counters(&Value::buff_from(data).unwrap()),
+            Some(vec![1, 258])
+        );
+        assert_eq!(parse_counters(&Value::buff_from(vec![1, 2]).unwrap()), None);
+    }
+
+    #[test]
+    fn lcov_record() {
+        let mut coverage = ContractCoverage::new(CoverageMap {
+            spans: vec![span(1), span(2), span(3), span(3)],
+            branches: vec![Branch {
+                span: span(1),
+                counters: vec![1, 2],
+            }],
+        });
+        coverage.add(&[2, 2, 0, 1]);
+        coverage.add(&[1, 0, 0, 0]);
+
+        assert_eq!(
+            coverage.lcov("contract.clar"),
+            "TN:\nSF:contract.clar\nBRDA:1,0,0,2\nBRDA:1,0,1,0\nBRF:2\nBRH:1\n\
+             DA:1,3\nDA:2,2\nDA:3,1\nLF:3\nLH:3\nend_of_record\n"
+        );
+    }
+}
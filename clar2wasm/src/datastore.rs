@@ -1,12 +1,13 @@
 //! The `datastore` module contains simple in-memory imnplementations of the
-//! various data storage traits used during program execution.
+//! various data storage traits used during program execution. A `Datastore`
+//! created with `Datastore::open` also persists its content to a SQLite file.
 //! It is intended for use in tooling and tests, but not intended to be used
 //! in production. The `datastore` module is only available when the
 //! `developer-mode` feature is enabled. Many of these methods are just
 //! mock implementations that do nothing.
 #![allow(clippy::expect_used, clippy::unwrap_used)]
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use clarity::types::chainstate::{
     BlockHeaderHash, BurnchainHeaderHash, ConsensusHash, SortitionId, StacksAddress, StacksBlockId,
@@ -19,10 +20,10 @@ use clarity::vm::database::{BurnStateDB, ClarityBackingStore, HeadersDB};
 use clarity::vm::errors::{InterpreterError, InterpreterResult as Result};
 use clarity::vm::types::{QualifiedContractIdentifier, TupleData};
 use clarity::vm::{StacksEpoch, Value};
-use rusqlite::Connection;
+use rusqlite::{params, Connection};
 use sha2::{Digest, Sha512_256};
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct Datastore {
     store: HashMap<StacksBlockId, HashMap<String, String>>,
     block_id_lookup: HashMap<StacksBlockId, StacksBlockId>,
@@ -31,21 +32,169 @@ pub struct Datastore {
     current_chain_tip: StacksBlockId,
     chain_height: u32,
     height_at_chain_tip: HashMap<StacksBlockId, u32>,
+    /// Stack of the blocks being built, with the state of the datastore
+    /// before each of them, to restore on rollback.
+    savepoints: Vec<(StacksBlockId, Datastore)>,
+    /// SQLite file to which every write is also applied, when the datastore
+    /// was created with `Datastore::open`.
+    db: Option<Connection>,
+}
+
+/// A clone only lives in memory: it does not write to the SQLite file of the
+/// original, so that the changes made to one of them, e.g. to a
+/// `TestEnvironment` checkpoint, cannot make the file diverge from the other.
+impl Clone for Datastore {
+    fn clone(&self) -> Self {
+        Datastore {
+            store: self.store.clone(),
+            block_id_lookup: self.block_id_lookup.clone(),
+            metadata: self.metadata.clone(),
+            open_chain_tip: self.open_chain_tip,
+            current_chain_tip: self.current_chain_tip,
+            chain_height: self.chain_height,
+            height_at_chain_tip: self.height_at_chain_tip.clone(),
+            savepoints: self.savepoints.clone(),
+            db: None,
+        }
+    }
+}
+
+/// Schema of the SQLite file of a persistent `Datastore`. The block IDs are
+/// stored as hex strings.
+const DATASTORE_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS data_table (
+        block_id TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (block_id, key)
+    );
+    CREATE TABLE IF NOT EXISTS block_lookup (
+        block_id TEXT PRIMARY KEY,
+        lookup_id TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS block_heights (
+        block_id TEXT PRIMARY KEY,
+        height INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS metadata_table (
+        contract TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (contract, key)
+    );
+    CREATE TABLE IF NOT EXISTS chain_tip (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        block_id TEXT NOT NULL,
+        height INTEGER NOT NULL
+    );
+";
+
+fn db_error(err: rusqlite::Error) -> clarity::vm::errors::Error {
+    InterpreterError::DBError(err.to_string()).into()
+}
+
+fn missing_block(block_id: &StacksBlockId) -> clarity::vm::errors::Error {
+    InterpreterError::Expect(format!("Block with ID {:?} does not exist", block_id)).into()
+}
+
+/// The error of `operation` on the savepoint stack when it is empty.
+fn missing_savepoint(operation: &str) -> clarity::vm::errors::Error {
+    InterpreterError::Expect(format!("{operation} called without a savepoint")).into()
+}
+
+fn id_from_hex(hex: &str) -> Result<StacksBlockId> {
+    StacksBlockId::from_hex(hex)
+        .map_err(|_| InterpreterError::DBError(format!("Invalid block ID {hex}")).into())
+}
+
+/// The differences between the content of two datastores.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DatastoreDiff {
+    /// Keys which were added, with their value.
+    pub added: BTreeMap<String, String>,
+    /// Keys whose value changed, with their old and new values.
+    pub modified: BTreeMap<String, (String, String)>,
+    /// Keys which were removed, with their old value.
+    pub removed: BTreeMap<String, String>,
+}
+
+impl DatastoreDiff {
+    fn new(before: &HashMap<String, String>, after: &HashMap<String, String>) -> Self {
+        let mut diff = DatastoreDiff::default();
+        for (key, value) in after {
+            match before.get(key) {
+                None => {
+                    diff.added.insert(key.clone(), value.clone());
+                }
+                Some(old) if old != value => {
+                    diff.modified
+                        .insert(key.clone(), (old.clone(), value.clone()));
+                }
+                Some(_) => (),
+            }
+        }
+        for (key, value) in before {
+            if !after.contains_key(key) {
+                diff.removed.insert(key.clone(), value.clone());
+            }
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.modified.is_empty() && self.removed.is_empty()
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct BlockInfo {
-    block_header_hash: BlockHeaderHash,
-    burn_block_header_hash: BurnchainHeaderHash,
-    consensus_hash: ConsensusHash,
-    vrf_seed: VRFSeed,
-    burn_block_time: u64,
-    burn_block_height: u32,
-    miner: StacksAddress,
-    burnchain_tokens_spent_for_block: u128,
-    get_burnchain_tokens_spent_for_winning_block: u128,
-    tokens_earned_for_block: u128,
-    pox_payout_addrs: (Vec<TupleData>, u128),
+    pub block_header_hash: BlockHeaderHash,
+    pub burn_block_header_hash: BurnchainHeaderHash,
+    pub consensus_hash: ConsensusHash,
+    pub vrf_seed: VRFSeed,
+    pub burn_block_time: u64,
+    pub burn_block_height: u32,
+    pub miner: StacksAddress,
+    pub burnchain_tokens_spent_for_block: u128,
+    pub burnchain_tokens_spent_for_winning_block: u128,
+    pub tokens_earned_for_block: u128,
+    pub pox_payout_addrs: (Vec<TupleData>, u128),
+}
+
+/// The settings used to generate the info of new blocks, when no info was
+/// set for their height with `BurnDatastore::set_block_info`.
+#[derive(Clone, Debug)]
+pub struct BlockSettings {
+    /// Seconds between the burn block times of two consecutive blocks.
+    pub time_spacing: u64,
+    pub miner: StacksAddress,
+    pub burnchain_tokens_spent_for_block: u128,
+    pub burnchain_tokens_spent_for_winning_block: u128,
+    pub tokens_earned_for_block: u128,
+    pub pox_payout_addrs: (Vec<TupleData>, u128),
+}
+
+impl Default for BlockSettings {
+    fn default() -> Self {
+        BlockSettings {
+            time_spacing: 1800,
+            miner: StacksAddress::burn_address(true),
+            burnchain_tokens_spent_for_block: 2000,
+            burnchain_tokens_spent_for_winning_block: 2000,
+            tokens_earned_for_block: 5000,
+            pox_payout_addrs: (
+                vec![TupleData::from_data(vec![
+                    (
+                        "hashbytes".into(),
+                        Value::buff_from([0; 32].to_vec()).unwrap(),
+                    ),
+                    ("version".into(), Value::buff_from_byte(0)),
+                ])
+                .unwrap()],
+                0_u128,
+            ),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -69,6 +218,9 @@ pub struct BurnDatastore {
     height_at_chain_tip: HashMap<StacksBlockId, u32>,
     constants: StacksConstants,
     genesis_time: u64,
+    settings: BlockSettings,
+    /// Block info set for heights which were not reached yet.
+    pending_blocks: HashMap<u32, BlockInfo>,
 }
 
 fn height_to_hashed_bytes(height: u32) -> [u8; 32] {
@@ -83,9 +235,10 @@ fn height_to_id(height: u32) -> StacksBlockId {
     StacksBlockId(height_to_hashed_bytes(height))
 }
 
-fn height_to_block(height: u32, genesis_time: Option<u64>) -> BlockInfo {
+/// The info of the block at `height`, derived from the height and `settings`,
+/// `burn_block_time` being the time of the block.
+fn height_to_block(height: u32, burn_block_time: u64, settings: &BlockSettings) -> BlockInfo {
     let bytes = height_to_hashed_bytes(height);
-    let genesis_time = genesis_time.unwrap_or(0);
 
     let block_header_hash = {
         let mut buffer = bytes;
@@ -107,24 +260,6 @@ fn height_to_block(height: u32, genesis_time: Option<u64>) -> BlockInfo {
         buffer[0] = 4;
         VRFSeed(buffer)
     };
-    let time_since_genesis: u64 = (height * 1800).into();
-    let burn_block_time: u64 = genesis_time + time_since_genesis;
-    let burn_block_height = height;
-    let miner = StacksAddress::burn_address(true);
-    let burnchain_tokens_spent_for_block = 2000;
-    let get_burnchain_tokens_spent_for_winning_block = 2000;
-    let tokens_earned_for_block = 5000;
-    let pox_payout_addrs = (
-        vec![TupleData::from_data(vec![
-            (
-                "hashbytes".into(),
-                Value::buff_from([0; 32].to_vec()).unwrap(),
-            ),
-            ("version".into(), Value::buff_from_byte(0)),
-        ])
-        .unwrap()],
-        0_u128,
-    );
 
     BlockInfo {
         block_header_hash,
@@ -132,12 +267,12 @@ fn height_to_block(height: u32, genesis_time: Option<u64>) -> BlockInfo {
         consensus_hash,
         vrf_seed,
         burn_block_time,
-        burn_block_height,
-        miner,
-        burnchain_tokens_spent_for_block,
-        get_burnchain_tokens_spent_for_winning_block,
-        tokens_earned_for_block,
-        pox_payout_addrs,
+        burn_block_height: height,
+        miner: settings.miner,
+        burnchain_tokens_spent_for_block: settings.burnchain_tokens_spent_for_block,
+        burnchain_tokens_spent_for_winning_block: settings.burnchain_tokens_spent_for_winning_block,
+        tokens_earned_for_block: settings.tokens_earned_for_block,
+        pox_payout_addrs: settings.pox_payout_addrs.clone(),
     }
 }
 
@@ -162,10 +297,12 @@ impl Datastore {
             current_chain_tip: id,
             chain_height: 0,
             height_at_chain_tip: id_height_map,
+            savepoints: Vec::new(),
+            db: None,
         }
     }
 
-    pub fn advance_chain_tip(&mut self, count: u32) -> u32 {
+    pub fn advance_chain_tip(&mut self, count: u32) -> Result<u32> {
         let cur_height = self.chain_height;
         let current_lookup_id = *self
             .block_id_lookup
@@ -178,12 +315,14 @@ impl Datastore {
 
             self.block_id_lookup.insert(id, current_lookup_id);
             self.height_at_chain_tip.insert(id, height);
+            self.persist_block(&id, &current_lookup_id, height)?;
         }
 
         self.chain_height += count;
         self.open_chain_tip = height_to_id(self.chain_height);
         self.current_chain_tip = self.open_chain_tip;
-        self.chain_height
+        self.persist_chain_tip()?;
+        Ok(self.chain_height)
     }
 }
 
@@ -196,7 +335,7 @@ impl Default for Datastore {
 impl ClarityBackingStore for Datastore {
     fn put_all(&mut self, items: Vec<(String, String)>) -> Result<()> {
         for (key, value) in items {
-            self.put(&key, &value);
+            self.put(&key, &value)?;
         }
         Ok(())
     }
@@ -271,7 +410,10 @@ impl ClarityBackingStore for Datastore {
     ) -> Result<()> {
         self.metadata
             .insert((contract.to_string(), key.to_string()), value.to_string());
-        Ok(())
+        self.execute(
+            "INSERT OR REPLACE INTO metadata_table (contract, key, value) VALUES (?1, ?2, ?3)",
+            params![contract.to_string(), key, value],
+        )
     }
 
     fn get_metadata(
@@ -309,7 +451,10 @@ impl ClarityBackingStore for Datastore {
 
     #[cfg(not(feature = "wasm"))]
     fn get_side_store(&mut self) -> &Connection {
-        panic!("Datastore cannot get_side_store")
+        match &self.db {
+            Some(db) => db,
+            None => panic!("Datastore cannot get_side_store"),
+        }
     }
 }
 
@@ -329,7 +474,7 @@ impl BurnDatastore {
             burn_block_height: 0,
             miner: StacksAddress::burn_address(false),
             burnchain_tokens_spent_for_block: 0,
-            get_burnchain_tokens_spent_for_winning_block: 0,
+            burnchain_tokens_spent_for_winning_block: 0,
             tokens_earned_for_block: 0,
             pox_payout_addrs: (
                 vec![TupleData::from_data(vec![
@@ -373,6 +518,8 @@ impl BurnDatastore {
             height_at_chain_tip,
             constants,
             genesis_time,
+            settings: BlockSettings::default(),
+            pending_blocks: HashMap::new(),
         }
     }
 
@@ -382,14 +529,22 @@ impl BurnDatastore {
             .block_id_lookup
             .get(&self.open_chain_tip)
             .expect("Open chain tip missing in block id lookup table");
-        let genesis_time = self.genesis_time;
+        let mut burn_block_time = self
+            .store
+            .get(&self.open_chain_tip)
+            .map_or(self.genesis_time, |block_info| block_info.burn_block_time);
 
         for i in 1..=count {
             let height = cur_height + i;
             let bytes = height_to_hashed_bytes(height);
             let id = StacksBlockId(bytes);
             let sortition_id = SortitionId(bytes);
-            let block_info = height_to_block(height, Some(genesis_time));
+            burn_block_time += self.settings.time_spacing;
+            let block_info = self
+                .pending_blocks
+                .remove(&height)
+                .unwrap_or_else(|| height_to_block(height, burn_block_time, &self.settings));
+            burn_block_time = block_info.burn_block_time;
             self.block_id_lookup.insert(id, current_lookup_id);
             self.height_at_chain_tip.insert(id, height);
             self.sortition_lookup.insert(sortition_id, id);
@@ -402,6 +557,41 @@ impl BurnDatastore {
         self.open_chain_tip = height_to_id(self.chain_height);
         self.current_chain_tip = self.open_chain_tip;
     }
+
+    pub fn block_settings(&self) -> &BlockSettings {
+        &self.settings
+    }
+
+    /// Set the settings used to generate the info of the next blocks.
+    pub fn set_block_settings(&mut self, settings: BlockSettings) {
+        self.settings = settings;
+    }
+
+    pub fn get_block_info(&self, height: u32) -> Option<&BlockInfo> {
+        self.store
+            .get(&height_to_id(height))
+            .or_else(|| self.pending_blocks.get(&height))
+    }
+
+    /// Set the info of the block at `height`, either an existing block, or
+    /// one which will be created by `advance_chain_tip`. The burn block
+    /// height of the block is always `height`.
+    pub fn set_block_info(&mut self, height: u32, mut block_info: BlockInfo) {
+        block_info.burn_block_height = height;
+        let id = height_to_id(height);
+        match self.store.get_mut(&id) {
+            Some(existing) => {
+                let sortition_id = SortitionId(id.0);
+                self.consensus_hash_lookup.remove(&existing.consensus_hash);
+                self.consensus_hash_lookup
+                    .insert(block_info.consensus_hash, sortition_id);
+                *existing = block_info;
+            }
+            None => {
+                self.pending_blocks.insert(height, block_info);
+            }
+        }
+    }
 }
 
 impl HeadersDB for BurnDatastore {
@@ -442,7 +632,7 @@ impl HeadersDB for BurnDatastore {
     fn get_burnchain_tokens_spent_for_winning_block(&self, id_bhh: &StacksBlockId) -> Option<u128> {
         self.store
             .get(id_bhh)
-            .map(|id| id.get_burnchain_tokens_spent_for_winning_block)
+            .map(|id| id.burnchain_tokens_spent_for_winning_block)
     }
     fn get_tokens_earned_for_block(&self, id_bhh: &StacksBlockId) -> Option<u128> {
         self.store.get(id_bhh).map(|id| id.tokens_earned_for_block)
@@ -547,8 +737,222 @@ impl BurnStateDB for BurnDatastore {
 }
 
 impl Datastore {
-    pub fn open(_path_str: &str, _miner_tip: Option<&StacksBlockId>) -> Result<Datastore> {
-        Ok(Datastore::new())
+    /// Open the SQLite file at `path_str`, creating it if needed, and load
+    /// its content. The datastore then writes all of its changes to this
+    /// file. If `miner_tip` is given, it becomes the open chain tip.
+    pub fn open(path_str: &str, miner_tip: Option<&StacksBlockId>) -> Result<Datastore> {
+        let db = Connection::open(path_str).map_err(db_error)?;
+        db.execute_batch(DATASTORE_SCHEMA).map_err(db_error)?;
+
+        let mut datastore = Datastore::new();
+        let tip: Option<(String, u32)> = db
+            .query_row("SELECT block_id, height FROM chain_tip", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .map_or_else(
+                |err| match err {
+                    rusqlite::Error::QueryReturnedNoRows => Ok(None),
+                    err => Err(db_error(err)),
+                },
+                |tip| Ok(Some(tip)),
+            )?;
+        if let Some((block_id, height)) = &tip {
+            datastore.load(&db)?;
+            datastore.open_chain_tip = id_from_hex(block_id)?;
+            datastore.current_chain_tip = datastore.open_chain_tip;
+            datastore.chain_height = *height;
+        }
+        datastore.db = Some(db);
+
+        // A new file starts with the genesis block
+        if tip.is_none() {
+            let genesis = datastore.open_chain_tip;
+            datastore.persist_block(&genesis, &genesis, 0)?;
+            datastore.persist_chain_tip()?;
+        }
+
+        if let Some(miner_tip) = miner_tip {
+            let height = *datastore
+                .height_at_chain_tip
+                .get(miner_tip)
+                .ok_or_else(|| {
+                    InterpreterError::DBError(format!(
+                        "Block with ID {:?} does not exist",
+                        miner_tip
+                    ))
+                })?;
+            datastore.open_chain_tip = *miner_tip;
+            datastore.current_chain_tip = *miner_tip;
+            datastore.chain_height = height;
+            datastore.persist_chain_tip()?;
+        }
+
+        Ok(datastore)
+    }
+
+    /// Replace the content of the datastore with the content of the SQLite
+    /// file `db`.
+    fn load(&mut self, db: &Connection) -> Result<()> {
+        self.store.clear();
+        self.block_id_lookup.clear();
+        self.height_at_chain_tip.clear();
+        self.metadata.clear();
+
+        let mut stmt = db
+            .prepare("SELECT block_id, lookup_id FROM block_lookup")
+            .map_err(db_error)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .map_err(db_error)?;
+        for row in rows {
+            let (block_id, lookup_id) = row.map_err(db_error)?;
+            let (block_id, lookup_id) = (id_from_hex(&block_id)?, id_from_hex(&lookup_id)?);
+            self.block_id_lookup.insert(block_id, lookup_id);
+            if block_id == lookup_id {
+                self.store.insert(block_id, HashMap::new());
+            }
+        }
+
+        let mut stmt = db
+            .prepare("SELECT block_id, height FROM block_heights")
+            .map_err(db_error)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, u32>(1)?))
+            })
+            .map_err(db_error)?;
+        for row in rows {
+            let (block_id, height) = row.map_err(db_error)?;
+            self.height_at_chain_tip
+                .insert(id_from_hex(&block_id)?, height);
+        }
+
+        let mut stmt = db
+            .prepare("SELECT block_id, key, value FROM data_table")
+            .map_err(db_error)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })
+            .map_err(db_error)?;
+        for row in rows {
+            let (block_id, key, value) = row.map_err(db_error)?;
+            self.store
+                .entry(id_from_hex(&block_id)?)
+                .or_default()
+                .insert(key, value);
+        }
+
+        let mut stmt = db
+            .prepare("SELECT contract, key, value FROM metadata_table")
+            .map_err(db_error)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })
+            .map_err(db_error)?;
+        for row in rows {
+            let (contract, key, value) = row.map_err(db_error)?;
+            self.metadata.insert((contract, key), value);
+        }
+
+        Ok(())
+    }
+
+    /// Run `sql` on the SQLite file, if the datastore has one.
+    fn execute<P: rusqlite::Params>(&self, sql: &str, params: P) -> Result<()> {
+        if let Some(db) = &self.db {
+            db.execute(sql, params).map_err(db_error)?;
+        }
+        Ok(())
+    }
+
+    fn persist_block(
+        &self,
+        block_id: &StacksBlockId,
+        lookup_id: &StacksBlockId,
+        height: u32,
+    ) -> Result<()> {
+        self.execute(
+            "INSERT OR REPLACE INTO block_lookup (block_id, lookup_id) VALUES (?1, ?2)",
+            params![block_id.to_hex(), lookup_id.to_hex()],
+        )?;
+        self.execute(
+            "INSERT OR REPLACE INTO block_heights (block_id, height) VALUES (?1, ?2)",
+            params![block_id.to_hex(), height],
+        )
+    }
+
+    fn persist_chain_tip(&self) -> Result<()> {
+        self.execute(
+            "INSERT OR REPLACE INTO chain_tip (id, block_id, height) VALUES (0, ?1, ?2)",
+            params![self.open_chain_tip.to_hex(), self.chain_height],
+        )
+    }
+
+    /// Rewrite the whole SQLite file, if the datastore has one, from the
+    /// content of the datastore.
+    fn persist_all(&self) -> Result<()> {
+        if self.db.is_none() {
+            return Ok(());
+        }
+
+        self.execute_batch(
+            "SAVEPOINT persist_all;
+            DELETE FROM data_table;
+            DELETE FROM block_lookup;
+            DELETE FROM block_heights;
+            DELETE FROM metadata_table;",
+        )?;
+        for (block_id, lookup_id) in &self.block_id_lookup {
+            self.execute(
+                "INSERT INTO block_lookup (block_id, lookup_id) VALUES (?1, ?2)",
+                params![block_id.to_hex(), lookup_id.to_hex()],
+            )?;
+        }
+        for (block_id, height) in &self.height_at_chain_tip {
+            self.execute(
+                "INSERT INTO block_heights (block_id, height) VALUES (?1, ?2)",
+                params![block_id.to_hex(), height],
+            )?;
+        }
+        for (block_id, map) in &self.store {
+            let block_id = block_id.to_hex();
+            for (key, value) in map {
+                self.execute(
+                    "INSERT INTO data_table (block_id, key, value) VALUES (?1, ?2, ?3)",
+                    params![block_id, key, value],
+                )?;
+            }
+        }
+        for ((contract, key), value) in &self.metadata {
+            self.execute(
+                "INSERT INTO metadata_table (contract, key, value) VALUES (?1, ?2, ?3)",
+                params![contract, key, value],
+            )?;
+        }
+        self.persist_chain_tip()?;
+        self.execute_batch("RELEASE persist_all")
+    }
+
+    /// Replace the content of the datastore with the content of `snapshot`,
+    /// e.g. a clone saved earlier, and rewrite its SQLite file, if it has
+    /// one, to match.
+    pub fn restore(&mut self, snapshot: &Datastore) -> Result<()> {
+        let db = self.db.take();
+        *self = snapshot.clone();
+        self.db = db;
+        self.persist_all()
     }
 
     pub fn as_analysis_db(&mut self) -> AnalysisDatabase<'_> {
@@ -564,24 +968,186 @@ impl Datastore {
     /// this is a "lower-level" rollback than the roll backs performed in
     ///   ClarityDatabase or AnalysisDatabase -- this is done at the backing store level.
 
-    pub fn begin(&mut self, _current: &StacksBlockId, _next: &StacksBlockId) {}
-    pub fn rollback(&mut self) {}
-    pub fn commit_mined_block(&mut self, _will_move_to: &StacksBlockId) {}
-    pub fn commit_to(&mut self, _final_bhh: &StacksBlockId) {}
+    pub fn begin(&mut self, current: &StacksBlockId, next: &StacksBlockId) -> Result<()> {
+        // The new block starts with the data of `current`
+        let current_lookup_id = *self
+            .block_id_lookup
+            .get(current)
+            .ok_or_else(|| missing_block(current))?;
+
+        // Save the state before the block, without the savepoints themselves
+        let savepoints = std::mem::take(&mut self.savepoints);
+        let snapshot = self.clone();
+        self.savepoints = savepoints;
+        self.savepoints.push((*next, snapshot));
+        self.execute_batch("SAVEPOINT block")?;
+
+        let height = self
+            .height_at_chain_tip
+            .get(current)
+            .map_or(self.chain_height, |height| height + 1);
+
+        self.block_id_lookup.insert(*next, current_lookup_id);
+        self.height_at_chain_tip.insert(*next, height);
+        self.chain_height = height;
+        self.open_chain_tip = *next;
+        self.current_chain_tip = *next;
+        self.persist_block(next, &current_lookup_id, height)?;
+        self.persist_chain_tip()
+    }
+
+    /// Discard the block being built, restoring the state exactly as it was
+    /// before the matching `begin`.
+    pub fn rollback(&mut self) -> Result<()> {
+        let (_, snapshot) = self
+            .savepoints
+            .pop()
+            .ok_or_else(|| missing_savepoint("rollback"))?;
+        let savepoints = std::mem::take(&mut self.savepoints);
+        let db = self.db.take();
+        *self = snapshot;
+        self.savepoints = savepoints;
+        self.db = db;
+        self.execute_batch("ROLLBACK TO block; RELEASE block")
+    }
+
+    /// Save the block being built as `will_move_to`, then return to the
+    /// chain tip from before the block, as a miner does with the blocks it
+    /// mines until they are accepted. The metadata is not kept by block, so
+    /// the metadata written in the block, like the analysis of the contracts
+    /// it deploys, is kept.
+    pub fn commit_mined_block(&mut self, will_move_to: &StacksBlockId) -> Result<()> {
+        let (block_id, snapshot) = self
+            .savepoints
+            .pop()
+            .ok_or_else(|| missing_savepoint("commit_mined_block"))?;
+        self.move_block(&block_id, will_move_to)?;
+
+        self.open_chain_tip = snapshot.open_chain_tip;
+        self.current_chain_tip = snapshot.current_chain_tip;
+        self.chain_height = snapshot.chain_height;
+        self.persist_chain_tip()?;
+        self.execute_batch("RELEASE block")
+    }
+
+    /// Save the block being built as `final_bhh`, which becomes the chain
+    /// tip.
+    pub fn commit_to(&mut self, final_bhh: &StacksBlockId) -> Result<()> {
+        let (block_id, _) = self
+            .savepoints
+            .pop()
+            .ok_or_else(|| missing_savepoint("commit_to"))?;
+        self.move_block(&block_id, final_bhh)?;
+
+        self.open_chain_tip = *final_bhh;
+        self.current_chain_tip = *final_bhh;
+        self.persist_chain_tip()?;
+        self.execute_batch("RELEASE block")
+    }
+
+    /// Move the data of the block `from` to the block `to`.
+    fn move_block(&mut self, from: &StacksBlockId, to: &StacksBlockId) -> Result<()> {
+        if from == to {
+            return Ok(());
+        }
+
+        let lookup_id = self
+            .block_id_lookup
+            .remove(from)
+            .ok_or_else(|| missing_block(from))?;
+        if lookup_id == *from {
+            // The block has its own data
+            let map = self.store.remove(from).ok_or_else(|| missing_block(from))?;
+            self.store.insert(*to, map);
+            self.block_id_lookup.insert(*to, *to);
+            // The blocks which share the data of `from` now share it from `to`
+            for lookup_id in self.block_id_lookup.values_mut() {
+                if lookup_id == from {
+                    *lookup_id = *to;
+                }
+            }
+        } else {
+            self.block_id_lookup.insert(*to, lookup_id);
+        }
+
+        if let Some(height) = self.height_at_chain_tip.remove(from) {
+            self.height_at_chain_tip.insert(*to, height);
+        }
+
+        let (from, to) = (from.to_hex(), to.to_hex());
+        self.execute(
+            "UPDATE data_table SET block_id = ?2 WHERE block_id = ?1",
+            params![from, to],
+        )?;
+        self.execute(
+            "UPDATE block_lookup SET lookup_id = ?2 WHERE lookup_id = ?1",
+            params![from, to],
+        )?;
+        self.execute(
+            "UPDATE block_lookup SET block_id = ?2 WHERE block_id = ?1",
+            params![from, to],
+        )?;
+        self.execute(
+            "UPDATE block_heights SET block_id = ?2 WHERE block_id = ?1",
+            params![from, to],
+        )
+    }
+
+    /// Run the SQL statements `sql` on the SQLite file, if the datastore has
+    /// one.
+    fn execute_batch(&self, sql: &str) -> Result<()> {
+        if let Some(db) = &self.db {
+            db.execute_batch(sql).map_err(db_error)?;
+        }
+        Ok(())
+    }
 
     pub fn get_chain_tip(&self) -> &StacksBlockId {
         &self.current_chain_tip
     }
 
+    pub fn get_chain_height(&self) -> u32 {
+        self.chain_height
+    }
+
+    /// The data visible from the current chain tip.
+    fn data_at_chain_tip(&self) -> &HashMap<String, String> {
+        let lookup_id = self
+            .block_id_lookup
+            .get(&self.current_chain_tip)
+            .expect("Could not find current chain tip in block_id_lookup map");
+        self.store
+            .get(lookup_id)
+            .unwrap_or_else(|| panic!("Block with ID {:?} does not exist", lookup_id))
+    }
+
+    /// The changes to the data visible from the current chain tip since
+    /// `before`.
+    pub fn diff(&self, before: &Datastore) -> DatastoreDiff {
+        DatastoreDiff::new(before.data_at_chain_tip(), self.data_at_chain_tip())
+    }
+
+    /// The changes to the contract metadata since `before`. The keys are
+    /// prefixed by the contract identifier, as `<contract>::<key>`.
+    pub fn diff_metadata(&self, before: &Datastore) -> DatastoreDiff {
+        let flatten = |metadata: &HashMap<(String, String), String>| {
+            metadata
+                .iter()
+                .map(|((contract, key), value)| (format!("{contract}::{key}"), value.clone()))
+                .collect::<HashMap<_, _>>()
+        };
+        DatastoreDiff::new(&flatten(&before.metadata), &flatten(&self.metadata))
+    }
+
     pub fn set_chain_tip(&mut self, bhh: &StacksBlockId) {
         self.current_chain_tip = *bhh;
     }
 
-    pub fn put(&mut self, key: &str, value: &str) {
+    pub fn put(&mut self, key: &str, value: &str) -> Result<()> {
         let lookup_id = self
             .block_id_lookup
             .get(&self.open_chain_tip)
-            .expect("Could not find current chain tip in block_id_lookup map");
+            .ok_or_else(|| missing_block(&self.open_chain_tip))?;
 
         // if there isn't a store for the open chain_tip, make one and update the
         // entry for the block id in the lookup table
@@ -590,22 +1156,261 @@ impl Datastore {
                 self.open_chain_tip,
                 self.store
                     .get(lookup_id)
-                    .unwrap_or_else(|| panic!("Block with ID {:?} does not exist", lookup_id))
+                    .ok_or_else(|| missing_block(lookup_id))?
                     .clone(),
             );
 
+            let open_chain_tip = self.open_chain_tip.to_hex();
+            self.execute(
+                "INSERT OR REPLACE INTO data_table (block_id, key, value)
+                    SELECT ?1, key, value FROM data_table WHERE block_id = ?2",
+                params![open_chain_tip, lookup_id.to_hex()],
+            )?;
+            self.execute(
+                "UPDATE block_lookup SET lookup_id = ?1 WHERE block_id = ?1",
+                params![open_chain_tip],
+            )?;
+
             self.block_id_lookup
-                .insert(self.open_chain_tip, self.current_chain_tip);
+                .insert(self.open_chain_tip, self.open_chain_tip);
         }
 
-        if let Some(map) = self.store.get_mut(&self.open_chain_tip) {
-            map.insert(key.to_string(), value.to_string());
-        } else {
-            panic!("Block does not exist for current chain tip");
-        }
+        self.store
+            .get_mut(&self.open_chain_tip)
+            .ok_or_else(|| missing_block(&self.open_chain_tip))?
+            .insert(key.to_string(), value.to_string());
+        self.execute(
+            "INSERT OR REPLACE INTO data_table (block_id, key, value) VALUES (?1, ?2, ?3)",
+            params![self.open_chain_tip.to_hex(), key, value],
+        )
     }
 
     pub fn make_contract_hash_key(contract: &QualifiedContractIdentifier) -> String {
         format!("clarity-contract::{}", contract)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use clarity::types::chainstate::StacksBlockId;
+    use clarity::vm::database::ClarityBackingStore;
+    use clarity::vm::types::QualifiedContractIdentifier;
+
+    use super::{height_to_id, Datastore};
+
+    #[test]
+    fn rollback_restores_state() {
+        let mut datastore = Datastore::new();
+        datastore.put("key", "before").unwrap();
+        let genesis = height_to_id(0);
+        let next = StacksBlockId([0xaa; 32]);
+
+        datastore.begin(&genesis, &next).unwrap();
+        datastore.put("key", "after").unwrap();
+        datastore.put("other", "value").unwrap();
+        assert_eq!(datastore.get("key").unwrap().as_deref(), Some("after"));
+        assert_eq!(datastore.get_open_chain_tip_height(), 1);
+
+        datastore.rollback().unwrap();
+        assert_eq!(datastore.get("key").unwrap().as_deref(), Some("before"));
+        assert_eq!(datastore.get("other").unwrap(), None);
+        assert_eq!(datastore.get_open_chain_tip(), genesis);
+        assert_eq!(datastore.get_open_chain_tip_height(), 0);
+    }
+
+    #[test]
+    fn commit_to_moves_the_chain_tip() {
+        let mut datastore = Datastore::new();
+        datastore.put("key", "before").unwrap();
+        let genesis = height_to_id(0);
+        let next = StacksBlockId([0xaa; 32]);
+        let last = StacksBlockId([0xbb; 32]);
+
+        datastore.begin(&genesis, &next).unwrap();
+        datastore.put("key", "after").unwrap();
+        datastore.commit_to(&last).unwrap();
+
+        assert_eq!(datastore.get_open_chain_tip(), last);
+        assert_eq!(datastore.get_open_chain_tip_height(), 1);
+        assert_eq!(datastore.get("key").unwrap().as_deref(), Some("after"));
+
+        // The parent block is unchanged
+        datastore.set_chain_tip(&genesis);
+        assert_eq!(datastore.get("key").unwrap().as_deref(), Some("before"));
+    }
+
+    #[test]
+    fn commit_to_moves_the_shared_data() {
+        let mut datastore = Datastore::new();
+        let genesis = height_to_id(0);
+        let next = StacksBlockId([0xaa; 32]);
+        let last = StacksBlockId([0xbb; 32]);
+
+        datastore.begin(&genesis, &next).unwrap();
+        datastore.put("key", "value").unwrap();
+        // The new block shares the data of `next`
+        datastore.advance_chain_tip(1).unwrap();
+        datastore.commit_to(&last).unwrap();
+
+        datastore.set_chain_tip(&height_to_id(2));
+        assert_eq!(datastore.get("key").unwrap().as_deref(), Some("value"));
+    }
+
+    #[test]
+    fn commit_mined_block_keeps_the_chain_tip() {
+        let mut datastore = Datastore::new();
+        datastore.put("key", "before").unwrap();
+        let genesis = height_to_id(0);
+        let next = StacksBlockId([0xaa; 32]);
+        let mined = StacksBlockId([0xcc; 32]);
+
+        datastore.begin(&genesis, &next).unwrap();
+        datastore.put("key", "after").unwrap();
+        datastore.commit_mined_block(&mined).unwrap();
+
+        assert_eq!(datastore.get_open_chain_tip(), genesis);
+        assert_eq!(datastore.get("key").unwrap().as_deref(), Some("before"));
+
+        datastore.set_chain_tip(&mined);
+        assert_eq!(datastore.get("key").unwrap().as_deref(), Some("after"));
+    }
+
+    #[test]
+    fn commit_mined_block_keeps_the_deployed_contracts() {
+        let mut datastore = Datastore::new();
+        let contract = QualifiedContractIdentifier::transient();
+        let genesis = height_to_id(0);
+        let next = StacksBlockId([0xaa; 32]);
+        let mined = StacksBlockId([0xcc; 32]);
+
+        // Deploy the contract in the mined block
+        datastore.begin(&genesis, &next).unwrap();
+        datastore
+            .put(&Datastore::make_contract_hash_key(&contract), "hash")
+            .unwrap();
+        datastore
+            .insert_metadata(&contract, "contract-src", "(+ 1 2)")
+            .unwrap();
+        datastore.commit_mined_block(&mined).unwrap();
+
+        datastore.set_chain_tip(&mined);
+        assert_eq!(
+            datastore
+                .get(&Datastore::make_contract_hash_key(&contract))
+                .unwrap()
+                .as_deref(),
+            Some("hash")
+        );
+        assert_eq!(
+            datastore
+                .get_metadata(&contract, "contract-src")
+                .unwrap()
+                .as_deref(),
+            Some("(+ 1 2)")
+        );
+    }
+
+    #[test]
+    fn savepoint_misuse_is_an_error() {
+        let mut datastore = Datastore::new();
+        assert!(datastore.rollback().is_err());
+        assert!(datastore.commit_to(&height_to_id(1)).is_err());
+        assert!(datastore
+            .commit_mined_block(&StacksBlockId([0xcc; 32]))
+            .is_err());
+        assert!(datastore
+            .begin(&StacksBlockId([0xbb; 32]), &StacksBlockId([0xaa; 32]))
+            .is_err());
+    }
+
+    #[test]
+    fn open_persists_to_file() {
+        let path =
+            std::env::temp_dir().join(format!("clar2wasm-datastore-{}.sqlite", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        let contract = QualifiedContractIdentifier::transient();
+        let next = StacksBlockId([0xaa; 32]);
+
+        {
+            let mut datastore = Datastore::open(path_str, None).unwrap();
+            datastore.put("key", "genesis").unwrap();
+            datastore.advance_chain_tip(2).unwrap();
+            datastore.put("key", "block 2").unwrap();
+            datastore
+                .insert_metadata(&contract, "meta", "data")
+                .unwrap();
+
+            datastore.begin(&height_to_id(2), &next).unwrap();
+            datastore.put("key", "discarded").unwrap();
+            datastore.rollback().unwrap();
+        }
+
+        let mut datastore = Datastore::open(path_str, None).unwrap();
+        assert_eq!(datastore.get_open_chain_tip(), height_to_id(2));
+        assert_eq!(datastore.get_open_chain_tip_height(), 2);
+        assert_eq!(datastore.get("key").unwrap().as_deref(), Some("block 2"));
+        assert_eq!(
+            datastore
+                .get_metadata(&contract, "meta")
+                .unwrap()
+                .as_deref(),
+            Some("data")
+        );
+
+        datastore.set_chain_tip(&height_to_id(1));
+        assert_eq!(datastore.get("key").unwrap().as_deref(), Some("genesis"));
+
+        let mut datastore = Datastore::open(path_str, Some(&height_to_id(1))).unwrap();
+        assert_eq!(datastore.get_open_chain_tip_height(), 1);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn restore_rewrites_file() {
+        let path = std::env::temp_dir().join(format!(
+            "clar2wasm-datastore-restore-{}.sqlite",
+            std::process::id()
+        ));
+        let path_str = path.to_str().unwrap();
+
+        {
+            let mut datastore = Datastore::open(path_str, None).unwrap();
+            datastore.put("key", "saved").unwrap();
+            let snapshot = datastore.clone();
+            datastore.put("key", "discarded").unwrap();
+            datastore.advance_chain_tip(1).unwrap();
+            datastore.restore(&snapshot).unwrap();
+            assert_eq!(datastore.get("key").unwrap().as_deref(), Some("saved"));
+        }
+
+        let mut datastore = Datastore::open(path_str, None).unwrap();
+        assert_eq!(datastore.get_open_chain_tip_height(), 0);
+        assert_eq!(datastore.get("key").unwrap().as_deref(), Some("saved"));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn clone_does_not_write_to_file() {
+        let path = std::env::temp_dir().join(format!(
+            "clar2wasm-datastore-clone-{}.sqlite",
+            std::process::id()
+        ));
+        let path_str = path.to_str().unwrap();
+
+        {
+            let mut datastore = Datastore::open(path_str, None).unwrap();
+            datastore.put("key", "original").unwrap();
+            let mut clone = datastore.clone();
+            clone.put("key", "clone").unwrap();
+            clone.advance_chain_tip(1).unwrap();
+        }
+
+        let mut datastore = Datastore::open(path_str, None).unwrap();
+        assert_eq!(datastore.get_open_chain_tip_height(), 0);
+        assert_eq!(datastore.get("key").unwrap().as_deref(), Some("original"));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
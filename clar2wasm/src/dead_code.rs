@@ -0,0 +1,205 @@
+//! The `dead_code` module removes the parts of the standard library which are
+//! not reachable from the contract: functions, host imports, globals, the
+//! hash160 function table and the constant data of the standard library.
+//!
+//! The standard library exports all of its functions, so these exports are
+//! removed first, leaving only the contract functions, `.top-level`, the
+//! memory and the globals read by the host as roots.
+
+use walrus::ir::{dfs_in_order, CallIndirect, Visitor};
+use walrus::{DataKind, ExportItem, Module};
+
+use crate::wasm_generator::END_OF_STANDARD_DATA;
+
+/// Prefix of the functions exported by the standard library.
+const STDLIB_PREFIX: &str = "stdlib.";
+
+/// Data segments of the standard library, given as a range of offsets, with
+/// the functions reading them. A segment is removed when none of these
+/// functions is reachable.
+const STANDARD_DATA_USERS: &[(u32, u32, &[&str])] = &[
+    (0, 288, &["stdlib.sha256-buf", "stdlib.sha256-int"]),
+    (288, 648, &["hash160-compress"]),
+    (648, 1352, &["stdlib.sha512-buf", "stdlib.sha512-int"]),
+    (1352, END_OF_STANDARD_DATA, &["stdlib.runtime-error"]),
+];
+
+/// Finds whether a function contains a `call_indirect` instruction.
+#[derive(Default)]
+struct CallIndirectFinder {
+    found: bool,
+}
+
+impl<'instr> Visitor<'instr> for CallIndirectFinder {
+    fn visit_call_indirect(&mut self, _instr: &CallIndirect) {
+        self.found = true;
+    }
+}
+
+/// Remove the parts of the standard library which are not used by the
+/// contract from `module`.
+pub fn eliminate_dead_code(module: &mut Module) {
+    // Only keep the exports of the contract.
+    let stdlib_exports: Vec<_> = module
+        .exports
+        .iter()
+        .filter(|export| {
+            matches!(export.item, ExportItem::Function(_)) && export.name.starts_with(STDLIB_PREFIX)
+        })
+        .map(|export| export.id())
+        .collect();
+    for id in stdlib_exports {
+        module.exports.delete(id);
+    }
+
+    walrus::passes::gc::run(module);
+
+    // The function table is only used by the hash160 functions, through
+    // `call_indirect`, but its elements keep them alive.
+    let uses_table = module.funcs.iter_local().any(|(_, func)| {
+        let mut finder = CallIndirectFinder::default();
+        dfs_in_order(&mut finder, func, func.entry_block());
+        finder.found
+    });
+    if !uses_table {
+        let elements: Vec<_> = module.elements.iter().map(|elem| elem.id()).collect();
+        for id in elements {
+            module.elements.delete(id);
+        }
+        let tables: Vec<_> = module.tables.iter().map(|table| table.id()).collect();
+        for id in tables {
+            module.tables.delete(id);
+        }
+        walrus::passes::gc::run(module);
+    }
+
+    // Remove the constant data of the standard functions which were removed.
+    let unused_data: Vec<_> = module
+        .data
+        .iter()
+        .filter(|data| match &data.kind {
+            DataKind::Active(active) => match active.location {
+                walrus::ActiveDataLocation::Absolute(offset) => {
+                    STANDARD_DATA_USERS.iter().any(|(start, end, users)| {
+                        (*start..*end).contains(&offset)
+                            && users
+                                .iter()
+                                .all(|name| module.funcs.by_name(name).is_none())
+                    })
+                }
+                _ => false,
+            },
+            DataKind::Passive => false,
+        })
+        .map(|data| data.id())
+        .collect();
+    for id in unused_data {
+        for memory in module.memories.iter_mut() {
+            memory.data_segments.remove(&id);
+        }
+        module.data.delete(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clarity::types::StacksEpochId;
+    use clarity::vm::costs::LimitedCostTracker;
+    use clarity::vm::database::MemoryBackingStore;
+    use clarity::vm::types::QualifiedContractIdentifier;
+    use clarity::vm::ClarityVersion;
+    use walrus::{ActiveDataLocation, DataKind, Module};
+
+    use super::STANDARD_DATA_USERS;
+    use crate::wasm_generator::END_OF_STANDARD_DATA;
+    use crate::{compile_with_options, CompileOptions};
+
+    fn compile_snippet(snippet: &str, keep_unused_stdlib: bool) -> Module {
+        let mut datastore = MemoryBackingStore::new();
+        compile_with_options(
+            snippet,
+            &QualifiedContractIdentifier::transient(),
+            LimitedCostTracker::new_free(),
+            ClarityVersion::latest(),
+            StacksEpochId::latest(),
+            &mut datastore.as_analysis_db(),
+            &CompileOptions {
+                keep_unused_stdlib,
+                ..CompileOptions::default()
+            },
+        )
+        .expect("Failed to compile contract.")
+        .module
+    }
+
+    #[test]
+    fn standard_data_users_match_the_standard_library() {
+        let module = Module::from_buffer(include_bytes!("standard/standard.wasm"))
+            .expect("Failed to parse the standard library.");
+
+        // The ranges are contiguous and cover all of the standard data
+        let mut end = 0;
+        for (start, range_end, users) in STANDARD_DATA_USERS {
+            assert_eq!(*start, end);
+            assert!(start < range_end);
+            for name in *users {
+                assert!(module.funcs.by_name(name).is_some(), "missing {name}");
+            }
+            end = *range_end;
+        }
+        assert_eq!(end, END_OF_STANDARD_DATA);
+
+        // Each data segment lies in a single range, and the segments fill
+        // the ranges
+        let mut covered = 0;
+        for data in module.data.iter() {
+            let DataKind::Active(active) = &data.kind else {
+                panic!("unexpected passive data segment");
+            };
+            let ActiveDataLocation::Absolute(offset) = active.location else {
+                panic!("unexpected relative data segment");
+            };
+            let data_end = offset + data.value.len() as u32;
+            assert!(
+                STANDARD_DATA_USERS
+                    .iter()
+                    .any(|(start, end, _)| *start <= offset && data_end <= *end),
+                "data segment {offset}..{data_end} is not in a single range"
+            );
+            covered += data_end - offset;
+        }
+        assert_eq!(covered, END_OF_STANDARD_DATA);
+    }
+
+    #[test]
+    fn unused_stdlib_is_removed() {
+        let mut module = compile_snippet("(define-read-only (add) (+ 1 2))", false);
+
+        assert!(module.funcs.by_name("stdlib.add-int").is_some());
+        assert!(module.funcs.by_name("stdlib.sha256-buf").is_none());
+        assert!(module.funcs.by_name("hash160-f1").is_none());
+        assert_eq!(module.tables.iter().count(), 0);
+        assert!(module
+            .exports
+            .iter()
+            .all(|export| !export.name.starts_with("stdlib.")));
+        assert!(module.exports.iter().any(|export| export.name == "add"));
+        assert!(module
+            .exports
+            .iter()
+            .any(|export| export.name == "stack-pointer"));
+
+        let kept = compile_snippet("(define-read-only (add) (+ 1 2))", true).emit_wasm();
+        assert!(module.emit_wasm().len() < kept.len());
+    }
+
+    #[test]
+    fn used_hash_functions_are_kept() {
+        let module = compile_snippet("(define-read-only (hash) (hash160 0x00))", false);
+
+        assert!(module.funcs.by_name("stdlib.hash160-buf").is_some());
+        assert!(module.funcs.by_name("hash160-f1").is_some());
+        assert_eq!(module.tables.iter().count(), 1);
+        assert!(module.funcs.by_name("stdlib.sha512-buf").is_none());
+    }
+}
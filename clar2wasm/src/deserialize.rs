@@ -197,8 +197,8 @@ impl WasmGenerator {
                     let result_offset = self.module.locals.add(ValType::I32);
                     then.global_get(self.stack_pointer).local_tee(result_offset);
                     then.i32_const(STANDARD_PRINCIPAL_BYTES as i32)
-                        .binop(BinaryOp::I32Add)
-                        .global_set(self.stack_pointer);
+                        .binop(BinaryOp::I32Add);
+                    self.set_stack_pointer(then);
 
                     // Copy the principal to the destination
                     then.local_get(result_offset)
@@ -849,8 +849,8 @@ impl WasmGenerator {
             .local_get(length)
             .i32_const(element_size)
             .binop(BinaryOp::I32Mul)
-            .binop(BinaryOp::I32Add)
-            .global_set(self.stack_pointer);
+            .binop(BinaryOp::I32Add);
+        self.set_stack_pointer(block);
 
         // Update the offset to point to the first element
         block
@@ -1,17 +1,30 @@
 extern crate lazy_static;
 
+use std::collections::HashSet;
+
+use clarity::consts::{CHAIN_ID_MAINNET, CHAIN_ID_TESTNET};
 use clarity::types::StacksEpochId;
 use clarity::vm::analysis::{run_analysis, AnalysisDatabase, ContractAnalysis};
 use clarity::vm::ast::{build_ast_with_diagnostics, ContractAST};
 use clarity::vm::costs::{ExecutionCost, LimitedCostTracker};
-use clarity::vm::diagnostic::Diagnostic;
+use clarity::vm::diagnostic::{Diagnostic, Level};
+use clarity::vm::functions::NativeFunctions;
 use clarity::vm::types::QualifiedContractIdentifier;
-use clarity::vm::ClarityVersion;
+use clarity::vm::variables::NativeVariables;
+use clarity::vm::{ClarityVersion, SymbolicExpression, SymbolicExpressionType};
+use coverage::CoverageMap;
 pub use walrus::Module;
-use wasm_generator::{GeneratorError, WasmGenerator};
+use wasm_generator::{CompileStats, GeneratorError, GeneratorOutput, WasmGenerator};
 
+pub mod abi;
+mod constant_folding;
+pub mod coverage;
+pub mod dead_code;
 mod deserialize;
+pub mod listing;
+pub mod project;
 mod serialize;
+pub mod source_map;
 pub mod wasm_generator;
 mod words;
 
@@ -36,6 +49,12 @@ pub struct CompileResult {
     pub diagnostics: Vec<Diagnostic>,
     pub module: Module,
     pub contract_analysis: ContractAnalysis,
+    /// The spans of the counters of the module, if it is instrumented for
+    /// coverage.
+    pub coverage: Option<CoverageMap>,
+    /// Statistics of the generation, like the bytes saved by reusing
+    /// literals.
+    pub stats: CompileStats,
 }
 
 #[derive(Debug)]
@@ -47,13 +66,90 @@ pub enum CompileError {
     },
 }
 
+/// Options controlling the generated module.
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+    /// Keep the standard library functions, data and exports which are not
+    /// used by the contract, e.g. to call them directly while debugging.
+    pub keep_unused_stdlib: bool,
+    /// Instrument the module to count the executions of each expression,
+    /// see the `coverage` module.
+    pub coverage: bool,
+    /// Add a source map from the code of the generated functions to the
+    /// Clarity source, see the `source_map` module.
+    pub source_map: bool,
+    /// The network targeted by the contract. When set, `is-in-mainnet`,
+    /// `is-in-regtest` and `chain-id` are compiled as constants, and the
+    /// host imports returning them are removed. Otherwise, they are queried
+    /// from the host at runtime.
+    pub network: Option<TargetNetwork>,
+    /// Maximum number of 64 KiB pages the memory can grow to. The contract
+    /// raises a `MemoryLimitExceeded` runtime error when it needs more. When
+    /// unset, the memory can grow up to the limit of the host.
+    pub max_memory_pages: Option<u32>,
+}
+
+/// A network in which contracts are deployed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Regtest,
+}
+
+/// The network targeted by a contract, with its chain id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetNetwork {
+    pub network: Network,
+    pub chain_id: u32,
+}
+
+impl TargetNetwork {
+    /// `network` with its default chain id.
+    pub fn new(network: Network) -> Self {
+        let chain_id = match network {
+            Network::Mainnet => CHAIN_ID_MAINNET,
+            Network::Testnet | Network::Regtest => CHAIN_ID_TESTNET,
+        };
+        TargetNetwork { network, chain_id }
+    }
+
+    pub fn is_in_mainnet(&self) -> bool {
+        self.network == Network::Mainnet
+    }
+
+    pub fn is_in_regtest(&self) -> bool {
+        self.network == Network::Regtest
+    }
+}
+
 pub fn compile(
+    source: &str,
+    contract_id: &QualifiedContractIdentifier,
+    cost_tracker: LimitedCostTracker,
+    clarity_version: ClarityVersion,
+    epoch: StacksEpochId,
+    analysis_db: &mut AnalysisDatabase,
+) -> Result<CompileResult, CompileError> {
+    compile_with_options(
+        source,
+        contract_id,
+        cost_tracker,
+        clarity_version,
+        epoch,
+        analysis_db,
+        &CompileOptions::default(),
+    )
+}
+
+pub fn compile_with_options(
     source: &str,
     contract_id: &QualifiedContractIdentifier,
     mut cost_tracker: LimitedCostTracker,
     clarity_version: ClarityVersion,
     epoch: StacksEpochId,
     analysis_db: &mut AnalysisDatabase,
+    options: &CompileOptions,
 ) -> Result<CompileResult, CompileError> {
     // Parse the contract
     let (ast, mut diagnostics, success) = build_ast_with_diagnostics(
@@ -84,7 +180,8 @@ pub fn compile(
     ) {
         Ok(contract_analysis) => contract_analysis,
         Err((e, cost_track)) => {
-            diagnostics.push(Diagnostic::err(&e.err));
+            diagnostics.push(e.diagnostic);
+            diagnostics.extend(unavailable_words(&ast.expressions, clarity_version));
             return Err(CompileError::Generic {
                 ast,
                 diagnostics,
@@ -111,15 +208,51 @@ pub fn compile(
     }
 
     #[allow(clippy::expect_used)]
-    match WasmGenerator::new(contract_analysis.clone()).and_then(WasmGenerator::generate) {
-        Ok(module) => Ok(CompileResult {
-            ast,
-            diagnostics,
-            module,
-            contract_analysis,
-        }),
-        Err(e) => {
-            diagnostics.push(Diagnostic::err(&e));
+    match WasmGenerator::new(contract_analysis.clone())
+        .and_then(|generator| {
+            let generator = if options.coverage {
+                generator.with_coverage()
+            } else {
+                generator
+            };
+            let generator = if options.source_map {
+                generator.with_source_map()
+            } else {
+                generator
+            };
+            let generator = match options.max_memory_pages {
+                Some(pages) => generator.with_max_memory_pages(pages),
+                None => generator,
+            };
+            match options.network {
+                Some(network) => generator.with_network(network),
+                None => Ok(generator),
+            }
+        })
+        .map_err(|e| (e, None))
+        .and_then(WasmGenerator::generate_output)
+    {
+        Ok(GeneratorOutput {
+            mut module,
+            coverage,
+            stats,
+        }) => {
+            if !options.keep_unused_stdlib {
+                dead_code::eliminate_dead_code(&mut module);
+            }
+            Ok(CompileResult {
+                ast,
+                diagnostics,
+                module,
+                contract_analysis,
+                coverage,
+                stats,
+            })
+        }
+        Err((e, span)) => {
+            let mut diagnostic = Diagnostic::err(&e);
+            diagnostic.spans.extend(span);
+            diagnostics.push(diagnostic);
             Err(CompileError::Generic {
                 ast,
                 diagnostics,
@@ -134,6 +267,142 @@ pub fn compile(
     }
 }
 
+/// Report the native functions called and the native variables referenced in
+/// `expressions` which are not available in `clarity_version`. These are only
+/// reported after the analysis has failed, to explain errors like unresolved
+/// functions. Names which the contract binds itself are legal in older
+/// versions, so they are never reported.
+fn unavailable_words(
+    expressions: &[SymbolicExpression],
+    clarity_version: ClarityVersion,
+) -> Vec<Diagnostic> {
+    let mut bound = HashSet::new();
+    collect_bound_names(expressions, &mut bound);
+
+    let mut diagnostics = Vec::new();
+    collect_unavailable_words(expressions, clarity_version, &bound, &mut diagnostics);
+    diagnostics
+}
+
+/// Collect the names bound by the definitions, function signatures, `let`
+/// and `match` bindings and tuple keys in `expressions`.
+fn collect_bound_names<'a>(expressions: &'a [SymbolicExpression], bound: &mut HashSet<&'a str>) {
+    for expr in expressions {
+        let list = match expr.match_list() {
+            Some(list) => list,
+            None => continue,
+        };
+        let atom = |index: usize| Some(list.get(index)?.match_atom()?.as_str());
+        let sublist = |index: usize| list.get(index).and_then(|expr| expr.match_list());
+        match atom(0) {
+            Some(
+                "define-constant"
+                | "define-data-var"
+                | "define-map"
+                | "define-fungible-token"
+                | "define-non-fungible-token"
+                | "define-trait",
+            ) => bound.extend(atom(1)),
+            Some("define-public" | "define-private" | "define-read-only") => {
+                if let Some(signature) = sublist(1) {
+                    // The function name, then its parameters
+                    bound.extend(
+                        signature
+                            .first()
+                            .and_then(|name| name.match_atom())
+                            .map(|name| name.as_str()),
+                    );
+                    bound.extend(first_atoms(signature));
+                }
+            }
+            Some("let") => bound.extend(sublist(1).into_iter().flat_map(first_atoms)),
+            Some("tuple") => bound.extend(first_atoms(&list[1..])),
+            Some("match") => {
+                bound.extend(atom(2));
+                // A response match also binds the error.
+                if list.len() == 6 {
+                    bound.extend(atom(4));
+                }
+            }
+            _ => {}
+        }
+        collect_bound_names(list, bound);
+    }
+}
+
+/// The first atom of each list in `expressions`, like the names of bindings.
+fn first_atoms(expressions: &[SymbolicExpression]) -> impl Iterator<Item = &str> {
+    expressions
+        .iter()
+        .filter_map(|expr| Some(expr.match_list()?.first()?.match_atom()?.as_str()))
+}
+
+fn collect_unavailable_words(
+    expressions: &[SymbolicExpression],
+    clarity_version: ClarityVersion,
+    bound: &HashSet<&str>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for expr in expressions {
+        match &expr.expr {
+            SymbolicExpressionType::List(exprs) => {
+                // Only the atom in call position can name a native function.
+                let mut arguments = &exprs[..];
+                if let Some((function, rest)) = exprs.split_first() {
+                    if let Some(name) = function.match_atom() {
+                        let required_version = NativeFunctions::lookup_by_name(name)
+                            .map(|function| function.get_version());
+                        diagnostics.extend(unavailable_word(
+                            function,
+                            required_version,
+                            clarity_version,
+                            bound,
+                        ));
+                        arguments = rest;
+                    }
+                }
+                collect_unavailable_words(arguments, clarity_version, bound, diagnostics);
+            }
+            SymbolicExpressionType::Atom(name) => {
+                let required_version =
+                    NativeVariables::lookup_by_name(name).map(|variable| variable.get_version());
+                diagnostics.extend(unavailable_word(
+                    expr,
+                    required_version,
+                    clarity_version,
+                    bound,
+                ));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Report the atom `expr` if it names a native word which requires a newer
+/// version than `clarity_version` and is not bound by the contract.
+fn unavailable_word(
+    expr: &SymbolicExpression,
+    required_version: Option<ClarityVersion>,
+    clarity_version: ClarityVersion,
+    bound: &HashSet<&str>,
+) -> Option<Diagnostic> {
+    let name = expr.match_atom()?;
+    let required_version = required_version.filter(|version| *version > clarity_version)?;
+    if bound.contains(name.as_str()) {
+        return None;
+    }
+    Some(Diagnostic {
+        level: Level::Error,
+        message: format!(
+            "'{name}' is not available in {clarity_version}, it requires {required_version}"
+        ),
+        spans: vec![expr.span().clone()],
+        suggestion: Some(format!(
+            "compile the contract with {required_version} or later"
+        )),
+    })
+}
+
 pub fn compile_contract(contract_analysis: ContractAnalysis) -> Result<Module, GeneratorError> {
     let generator = WasmGenerator::new(contract_analysis)?;
     generator.generate()
@@ -195,3 +464,52 @@ mod utils {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use clarity::vm::database::MemoryBackingStore;
+
+    use super::*;
+
+    fn clarity1_diagnostics(source: &str) -> Vec<String> {
+        let mut datastore = MemoryBackingStore::new();
+        match compile(
+            source,
+            &QualifiedContractIdentifier::transient(),
+            LimitedCostTracker::new_free(),
+            ClarityVersion::Clarity1,
+            StacksEpochId::Epoch21,
+            &mut datastore.as_analysis_db(),
+        ) {
+            Err(CompileError::Generic { diagnostics, .. }) => diagnostics
+                .into_iter()
+                .map(|diagnostic| diagnostic.message)
+                .collect(),
+            Ok(_) => panic!("expected the compilation to fail"),
+        }
+    }
+
+    #[test]
+    fn unavailable_words_are_reported() {
+        let diagnostics = clarity1_diagnostics("(define-read-only (foo) (stx-account tx-sender))");
+        assert!(diagnostics
+            .iter()
+            .any(|message| message.starts_with("'stx-account' is not available in Clarity 1")));
+    }
+
+    #[test]
+    fn bound_names_are_not_reported_as_unavailable() {
+        // `stx-account` is only a native function from Clarity 2, so a
+        // Clarity 1 contract can bind it. The contract fails on `bar`.
+        let diagnostics = clarity1_diagnostics(
+            "
+(define-read-only (foo)
+  (let ((stx-account u1)) (+ stx-account (bar))))
+",
+        );
+        assert!(!diagnostics.is_empty());
+        assert!(!diagnostics
+            .iter()
+            .any(|message| message.contains("is not available")));
+    }
+}
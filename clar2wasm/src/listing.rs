@@ -0,0 +1,116 @@
+//! The `listing` module prints a generated module in the WebAssembly text
+//! format, annotated with the Clarity source of each top-level form, so that
+//! the generated code can be reviewed without external tools.
+
+use clarity::vm::{ClarityName, SymbolicExpression, SymbolicExpressionType};
+use walrus::Module;
+
+use crate::wasm_generator::GeneratorError;
+
+/// Returns the name of the function defined by a top-level `define-public`,
+/// `define-read-only` or `define-private` form.
+fn defined_function_name(expr: &SymbolicExpression) -> Option<&ClarityName> {
+    let (keyword, args) = expr.match_list()?.split_first()?;
+    match keyword.match_atom()?.as_str() {
+        "define-public" | "define-read-only" | "define-private" => {
+            args.first()?.match_list()?.first()?.match_atom()
+        }
+        _ => None,
+    }
+}
+
+/// Format `expr` as Clarity source, on a single line.
+fn clarity_source(expr: &SymbolicExpression) -> String {
+    match &expr.expr {
+        SymbolicExpressionType::List(exprs) => format!(
+            "({})",
+            exprs
+                .iter()
+                .map(clarity_source)
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+        SymbolicExpressionType::Atom(name) => name.to_string(),
+        SymbolicExpressionType::AtomValue(value) | SymbolicExpressionType::LiteralValue(value) => {
+            value.to_string()
+        }
+        _ => expr.to_string(),
+    }
+}
+
+/// Print `module` in the WebAssembly text format. Each function defined in
+/// `expressions` is preceded by a comment containing its Clarity definition,
+/// and the `.top-level` function is preceded by all of the top-level forms,
+/// in the order in which they are executed.
+pub fn annotated_wat(
+    module: &mut Module,
+    expressions: &[SymbolicExpression],
+) -> Result<String, GeneratorError> {
+    let wat = wasmprinter::print_bytes(module.emit_wasm()).map_err(|e| {
+        GeneratorError::InternalError(format!("failed to print module as text: {e}"))
+    })?;
+
+    let mut listing = String::with_capacity(wat.len());
+    for line in wat.lines() {
+        let trimmed = line.trim_start();
+        if let Some(func_name) = trimmed
+            .strip_prefix("(func $")
+            .and_then(|rest| rest.split_whitespace().next())
+        {
+            let indent = &line[..line.len() - trimmed.len()];
+            if func_name == ".top-level" {
+                for expr in expressions {
+                    listing.push_str(&format!("{indent};; {}\n", clarity_source(expr)));
+                }
+            } else if let Some(expr) = expressions
+                .iter()
+                .find(|expr| defined_function_name(expr).map(|n| n.as_str()) == Some(func_name))
+            {
+                listing.push_str(&format!("{indent};; {}\n", clarity_source(expr)));
+            }
+        }
+        listing.push_str(line);
+        listing.push('\n');
+    }
+
+    Ok(listing)
+}
+
+#[cfg(test)]
+mod tests {
+    use clarity::types::StacksEpochId;
+    use clarity::vm::costs::LimitedCostTracker;
+    use clarity::vm::database::MemoryBackingStore;
+    use clarity::vm::types::QualifiedContractIdentifier;
+    use clarity::vm::ClarityVersion;
+
+    use super::annotated_wat;
+    use crate::compile;
+
+    #[test]
+    fn listing_contains_clarity_forms() {
+        let snippet =
+            "(define-private (add-one (n int)) (let ((result (+ n 1))) result)) (add-one 41)";
+        let mut datastore = MemoryBackingStore::new();
+        let mut result = compile(
+            snippet,
+            &QualifiedContractIdentifier::transient(),
+            LimitedCostTracker::new_free(),
+            ClarityVersion::latest(),
+            StacksEpochId::latest(),
+            &mut datastore.as_analysis_db(),
+        )
+        .expect("Failed to compile contract.");
+
+        let wat = annotated_wat(&mut result.module, &result.ast.expressions).unwrap();
+
+        assert!(
+            wat.contains(";; (define-private (add-one (n int)) (let ((result (+ n 1))) result))")
+        );
+        assert!(wat.contains("(func $add-one"));
+        assert!(wat.contains(";; (add-one 41)"));
+        assert!(wat.contains("(func $.top-level"));
+        assert!(wat.contains("$result"));
+        assert!(wat.contains("$frame-pointer"));
+    }
+}
@@ -0,0 +1,417 @@
+//! The `project` module compiles a set of contracts which reference each
+//! other, with `contract-call?`, `use-trait` or `impl-trait`. The contracts
+//! are analyzed and compiled in dependency order, into a shared analysis
+//! database, so that each contract can be type-checked against the contracts
+//! it depends on.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use clarity::types::StacksEpochId;
+use clarity::vm::analysis::AnalysisDatabase;
+use clarity::vm::ast::build_ast_with_diagnostics;
+use clarity::vm::costs::LimitedCostTracker;
+use clarity::vm::diagnostic::Diagnostic;
+use clarity::vm::types::{PrincipalData, QualifiedContractIdentifier, StandardPrincipalData};
+use clarity::vm::{
+    ClarityVersion, ContractName, SymbolicExpression, SymbolicExpressionType, Value,
+};
+use serde::Deserialize;
+
+use crate::{compile_with_options, CompileError, CompileOptions, CompileResult};
+
+/// A contract of a project, with its source code.
+#[derive(Debug, Clone)]
+pub struct ProjectContract {
+    pub contract_id: QualifiedContractIdentifier,
+    pub path: PathBuf,
+    pub source: String,
+}
+
+#[derive(Debug)]
+pub enum ProjectError {
+    /// A file of the project could not be read.
+    Io(PathBuf, std::io::Error),
+    /// The manifest is not valid.
+    Manifest(String),
+    /// The contract cannot be parsed, so its dependencies are unknown.
+    Parse(QualifiedContractIdentifier, Vec<Diagnostic>),
+    /// The contracts depend on each other, in the listed order.
+    Cycle(Vec<QualifiedContractIdentifier>),
+    /// The contract failed to compile.
+    Compile(QualifiedContractIdentifier, Box<CompileError>),
+}
+
+impl std::fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProjectError::Io(path, error) => write!(f, "{}: {error}", path.display()),
+            ProjectError::Manifest(msg) => write!(f, "invalid manifest: {msg}"),
+            ProjectError::Parse(contract_id, _) => write!(f, "{contract_id}: failed to parse"),
+            ProjectError::Cycle(cycle) => write!(
+                f,
+                "dependency cycle: {}",
+                cycle
+                    .iter()
+                    .map(|id| id.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" -> ")
+            ),
+            ProjectError::Compile(contract_id, _) => write!(f, "{contract_id}: failed to compile"),
+        }
+    }
+}
+
+/// The subset of a Clarinet manifest used to locate the contracts.
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    contracts: HashMap<String, ManifestContract>,
+}
+
+#[derive(Deserialize)]
+struct ManifestContract {
+    path: PathBuf,
+}
+
+fn contract_name(name: &str) -> Result<ContractName, ProjectError> {
+    ContractName::try_from(name.to_string())
+        .map_err(|_| ProjectError::Manifest(format!("invalid contract name: {name}")))
+}
+
+fn read_source(path: &Path) -> Result<String, ProjectError> {
+    std::fs::read_to_string(path).map_err(|e| ProjectError::Io(path.to_path_buf(), e))
+}
+
+/// Collect the contracts referenced by `expressions`, as the target of a
+/// `contract-call?` or through a trait identifier.
+fn collect_references(
+    expressions: &[SymbolicExpression],
+    references: &mut BTreeSet<QualifiedContractIdentifier>,
+) {
+    for expr in expressions {
+        match &expr.expr {
+            SymbolicExpressionType::List(exprs) => {
+                if let [function, target, ..] = exprs.as_slice() {
+                    if function.match_atom().map(|name| name.as_str()) == Some("contract-call?") {
+                        if let SymbolicExpressionType::LiteralValue(Value::Principal(
+                            PrincipalData::Contract(contract_id),
+                        ))
+                        | SymbolicExpressionType::AtomValue(Value::Principal(
+                            PrincipalData::Contract(contract_id),
+                        )) = &target.expr
+                        {
+                            references.insert(contract_id.clone());
+                        }
+                    }
+                }
+                collect_references(exprs, references);
+            }
+            SymbolicExpressionType::Field(trait_identifier) => {
+                references.insert(trait_identifier.contract_identifier.clone());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A set of contracts deployed by the same principal.
+#[derive(Debug, Clone)]
+pub struct Project {
+    deployer: StandardPrincipalData,
+    contracts: Vec<ProjectContract>,
+}
+
+impl Project {
+    pub fn new(deployer: StandardPrincipalData) -> Self {
+        Self {
+            deployer,
+            contracts: Vec::new(),
+        }
+    }
+
+    /// Create a project from every `.clar` file in `dir`, named after their
+    /// file stem.
+    pub fn from_directory(
+        dir: &Path,
+        deployer: StandardPrincipalData,
+    ) -> Result<Self, ProjectError> {
+        let mut paths = std::fs::read_dir(dir)
+            .map_err(|e| ProjectError::Io(dir.to_path_buf(), e))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| ProjectError::Io(dir.to_path_buf(), e))?;
+        paths.retain(|path| path.extension().map_or(false, |ext| ext == "clar"));
+        paths.sort();
+
+        let mut project = Self::new(deployer);
+        for path in paths {
+            let name = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .ok_or_else(|| ProjectError::Manifest(format!("invalid file name: {path:?}")))?;
+            let name = contract_name(name)?;
+            let source = read_source(&path)?;
+            project.add_contract(name, path, source);
+        }
+        Ok(project)
+    }
+
+    /// Create a project from the `[contracts.<name>]` entries of a Clarinet
+    /// manifest. Contract paths are relative to the manifest.
+    pub fn from_manifest(
+        manifest_path: &Path,
+        deployer: StandardPrincipalData,
+    ) -> Result<Self, ProjectError> {
+        let manifest: Manifest = toml::from_str(&read_source(manifest_path)?)
+            .map_err(|e| ProjectError::Manifest(e.to_string()))?;
+        let base = manifest_path.parent().unwrap_or_else(|| Path::new("."));
+
+        let mut entries: Vec<_> = manifest.contracts.into_iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut project = Self::new(deployer);
+        for (name, contract) in entries {
+            let path = base.join(contract.path);
+            let source = read_source(&path)?;
+            project.add_contract(contract_name(&name)?, path, source);
+        }
+        Ok(project)
+    }
+
+    pub fn add_contract(&mut self, name: ContractName, path: PathBuf, source: String) {
+        self.contracts.push(ProjectContract {
+            contract_id: QualifiedContractIdentifier::new(self.deployer.clone(), name),
+            path,
+            source,
+        });
+    }
+
+    pub fn contracts(&self) -> &[ProjectContract] {
+        &self.contracts
+    }
+
+    /// Parse each contract and return the contracts of this project it
+    /// references, by index into `contracts()`.
+    fn dependencies(
+        &self,
+        clarity_version: ClarityVersion,
+        epoch: StacksEpochId,
+    ) -> Result<Vec<BTreeSet<usize>>, ProjectError> {
+        let indices: HashMap<_, _> = self
+            .contracts
+            .iter()
+            .enumerate()
+            .map(|(i, contract)| (&contract.contract_id, i))
+            .collect();
+
+        self.contracts
+            .iter()
+            .map(|contract| {
+                let (ast, diagnostics, success) = build_ast_with_diagnostics(
+                    &contract.contract_id,
+                    &contract.source,
+                    &mut LimitedCostTracker::new_free(),
+                    clarity_version,
+                    epoch,
+                );
+                if !success {
+                    return Err(ProjectError::Parse(
+                        contract.contract_id.clone(),
+                        diagnostics,
+                    ));
+                }
+
+                let mut references = BTreeSet::new();
+                collect_references(&ast.expressions, &mut references);
+                Ok(references
+                    .iter()
+                    .filter(|reference| **reference != contract.contract_id)
+                    .filter_map(|reference| indices.get(reference).copied())
+                    .collect())
+            })
+            .collect()
+    }
+
+    /// Returns the indices of the contracts, ordered so that every contract
+    /// comes after the contracts it depends on. Contracts which do not depend
+    /// on each other keep the order in which they were added.
+    pub fn compile_order(
+        &self,
+        clarity_version: ClarityVersion,
+        epoch: StacksEpochId,
+    ) -> Result<Vec<usize>, ProjectError> {
+        let dependencies = self.dependencies(clarity_version, epoch)?;
+
+        let mut order = Vec::with_capacity(dependencies.len());
+        let mut done = vec![false; dependencies.len()];
+        while order.len() < dependencies.len() {
+            let next = (0..dependencies.len())
+                .find(|&i| !done[i] && dependencies[i].iter().all(|&dep| done[dep]));
+            match next {
+                Some(i) => {
+                    done[i] = true;
+                    order.push(i);
+                }
+                None => return Err(ProjectError::Cycle(self.find_cycle(&dependencies, &done))),
+            }
+        }
+        Ok(order)
+    }
+
+    /// Follow the dependencies of the remaining contracts until one is
+    /// visited twice. Every remaining contract has a remaining dependency,
+    /// otherwise it could have been ordered.
+    fn find_cycle(
+        &self,
+        dependencies: &[BTreeSet<usize>],
+        done: &[bool],
+    ) -> Vec<QualifiedContractIdentifier> {
+        let mut path = Vec::new();
+        let mut current = done.iter().position(|d| !d);
+        while let Some(i) = current {
+            if let Some(start) = path.iter().position(|&p| p == i) {
+                path.push(i);
+                return path[start..]
+                    .iter()
+                    .map(|&p| self.contracts[p].contract_id.clone())
+                    .collect();
+            }
+            path.push(i);
+            current = dependencies[i].iter().copied().find(|&dep| !done[dep]);
+        }
+        Vec::new()
+    }
+
+    /// Compile every contract of the project, in dependency order. The
+    /// analysis of each contract is saved into `analysis_db`, so that the
+    /// contracts depending on it can be analyzed.
+    pub fn compile(
+        &self,
+        clarity_version: ClarityVersion,
+        epoch: StacksEpochId,
+        analysis_db: &mut AnalysisDatabase,
+        options: &CompileOptions,
+    ) -> Result<Vec<(&ProjectContract, CompileResult)>, ProjectError> {
+        let mut results = Vec::with_capacity(self.contracts.len());
+        for i in self.compile_order(clarity_version, epoch)? {
+            let contract = &self.contracts[i];
+            let result = compile_with_options(
+                &contract.source,
+                &contract.contract_id,
+                LimitedCostTracker::new_free(),
+                clarity_version,
+                epoch,
+                analysis_db,
+                options,
+            )
+            .map_err(|e| ProjectError::Compile(contract.contract_id.clone(), Box::new(e)))?;
+
+            analysis_db
+                .execute(|db| db.insert_contract(&contract.contract_id, &result.contract_analysis))
+                .map_err(|e| {
+                    ProjectError::Compile(
+                        contract.contract_id.clone(),
+                        Box::new(CompileError::Generic {
+                            ast: result.ast.clone(),
+                            diagnostics: vec![Diagnostic::err(&e.err)],
+                            cost_tracker: Box::new(LimitedCostTracker::new_free()),
+                        }),
+                    )
+                })?;
+
+            results.push((contract, result));
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clarity::types::StacksEpochId;
+    use clarity::vm::database::MemoryBackingStore;
+    use clarity::vm::types::StandardPrincipalData;
+    use clarity::vm::ClarityVersion;
+
+    use super::{Project, ProjectError};
+    use crate::CompileOptions;
+
+    fn project(contracts: &[(&str, &str)]) -> Project {
+        let mut project = Project::new(StandardPrincipalData::transient());
+        for (name, source) in contracts {
+            project.add_contract(
+                (*name).into(),
+                format!("{name}.clar").into(),
+                source.to_string(),
+            );
+        }
+        project
+    }
+
+    #[test]
+    fn compile_order_follows_dependencies() {
+        let project = project(&[
+            (
+                "caller",
+                "(define-public (call) (contract-call? .callee get))",
+            ),
+            (
+                "implementer",
+                "(impl-trait .traits.getter) (define-public (get) (ok u1))",
+            ),
+            ("callee", "(define-public (get) (ok u1))"),
+            (
+                "traits",
+                "(define-trait getter ((get () (response uint uint))))",
+            ),
+        ]);
+
+        let order = project
+            .compile_order(ClarityVersion::latest(), StacksEpochId::latest())
+            .unwrap();
+        assert_eq!(order, vec![2, 0, 3, 1]);
+    }
+
+    #[test]
+    fn compile_order_reports_cycles() {
+        let project = project(&[
+            ("a", "(define-public (call) (contract-call? .b call))"),
+            ("b", "(define-public (call) (contract-call? .a call))"),
+        ]);
+
+        match project.compile_order(ClarityVersion::latest(), StacksEpochId::latest()) {
+            Err(ProjectError::Cycle(cycle)) => {
+                let names: Vec<_> = cycle.iter().map(|id| id.name.to_string()).collect();
+                assert_eq!(names, vec!["a", "b", "a"]);
+            }
+            other => panic!("expected a cycle, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn compile_uses_shared_analysis() {
+        let project = project(&[
+            (
+                "caller",
+                "(define-public (call) (contract-call? .callee get))",
+            ),
+            ("callee", "(define-public (get) (ok u1))"),
+        ]);
+
+        let mut datastore = MemoryBackingStore::new();
+        let results = project
+            .compile(
+                ClarityVersion::latest(),
+                StacksEpochId::latest(),
+                &mut datastore.as_analysis_db(),
+                &CompileOptions::default(),
+            )
+            .unwrap();
+
+        let names: Vec<_> = results
+            .iter()
+            .map(|(contract, _)| contract.contract_id.name.to_string())
+            .collect();
+        assert_eq!(names, vec!["callee", "caller"]);
+    }
+}
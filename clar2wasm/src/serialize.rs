@@ -889,6 +889,25 @@ impl WasmGenerator {
         Ok(())
     }
 
+    /// Grows the memory so that a value of type `ty` can be serialized at the
+    /// offset stored in `offset_local`, whatever its actual size.
+    pub(crate) fn ensure_serialization_memory(
+        &self,
+        builder: &mut InstrSeqBuilder,
+        offset_local: LocalId,
+        ty: &TypeSignature,
+    ) -> Result<(), GeneratorError> {
+        let max_size = ty.max_serialized_size().map_err(|e| {
+            GeneratorError::TypeError(format!("cannot serialize a value of type {ty}: {e:?}"))
+        })?;
+        builder
+            .local_get(offset_local)
+            .i32_const(max_size as i32)
+            .binop(BinaryOp::I32Add);
+        self.ensure_memory(builder);
+        Ok(())
+    }
+
     /// Serialize the value of type `ty` on the top of the data stack using
     /// consensus serialization. Leaves the length of the data written on the
     /// top of the data stack. See SIP-005 for details.
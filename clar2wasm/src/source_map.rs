@@ -0,0 +1,337 @@
+//! The `source_map` module maps the code of the generated functions back to
+//! the spans of the Clarity expressions they were generated from, so that a
+//! trap can be reported at its location in the contract.
+//!
+//! While an expression is traversed, the instructions emitted for it are
+//! tagged with its span. When the module is emitted, the offsets of these
+//! instructions are written in the `clarity-source-map` custom section, as
+//! little-endian `u32`s:
+//!
+//! ```text
+//! version
+//! count
+//! count * (function index, offset, start line, start column, end line, end column)
+//! ```
+//!
+//! Offsets are relative to the start of the function body, like the
+//! offsets of the frames of a wasmtime backtrace.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use clarity::vm::representations::Span;
+use walrus::ir::InstrLocId;
+use walrus::{CodeTransform, CustomSection, FunctionId, IdsToIndices, Module};
+
+use crate::wasm_generator::GeneratorError;
+
+/// Name of the custom section holding the source map.
+pub const SOURCE_MAP_SECTION: &str = "clarity-source-map";
+
+/// Version of the format of the source map section.
+pub const SOURCE_MAP_VERSION: u32 = 1;
+
+/// The source map section, while the module is generated.
+#[derive(Debug, Default)]
+pub(crate) struct SourceMapSection {
+    /// The spans of the tagged instructions, indexed by their location ID.
+    spans: Vec<Span>,
+    /// The generated functions. The functions of the standard library have
+    /// their own location IDs, from their original binary.
+    functions: Vec<FunctionId>,
+    /// The mapped instructions, as function, offset and span index.
+    entries: Vec<(FunctionId, usize, usize)>,
+}
+
+impl SourceMapSection {
+    /// The location ID of the instructions generated for the expression at
+    /// `span`.
+    pub(crate) fn location(&mut self, span: &Span) -> InstrLocId {
+        self.spans.push(span.clone());
+        InstrLocId::new(self.spans.len() as u32 - 1)
+    }
+
+    pub(crate) fn add_function(&mut self, function: FunctionId) {
+        self.functions.push(function);
+    }
+}
+
+impl CustomSection for SourceMapSection {
+    fn name(&self) -> &str {
+        SOURCE_MAP_SECTION
+    }
+
+    fn data(&self, ids_to_indices: &IdsToIndices) -> Cow<[u8]> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&SOURCE_MAP_VERSION.to_le_bytes());
+        data.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        for (function, offset, span) in &self.entries {
+            let span = &self.spans[*span];
+            for value in [
+                ids_to_indices.get_func_index(*function),
+                *offset as u32,
+                span.start_line,
+                span.start_column,
+                span.end_line,
+                span.end_column,
+            ] {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        Cow::Owned(data)
+    }
+
+    fn apply_code_transform(&mut self, transform: &CodeTransform) {
+        self.entries.clear();
+        for (function, range) in &transform.function_ranges {
+            if !self.functions.contains(function) {
+                continue;
+            }
+            self.entries.extend(
+                transform
+                    .instruction_map
+                    .iter()
+                    .filter(|(loc, offset)| {
+                        (range.start..range.end).contains(offset)
+                            && (loc.data() as usize) < self.spans.len()
+                    })
+                    .map(|(loc, offset)| (*function, offset - range.start, loc.data() as usize)),
+            );
+        }
+    }
+}
+
+/// An instruction of a generated function, with the span of the expression
+/// it was generated from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceMapEntry {
+    pub func_index: u32,
+    pub offset: u32,
+    pub span: Span,
+}
+
+/// The source map read back from a generated module.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    /// The entries, sorted by function and offset.
+    pub entries: Vec<SourceMapEntry>,
+}
+
+/// A frame of a backtrace, located in the Clarity source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFrame {
+    /// The name of the function, if known.
+    pub function: Option<String>,
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl fmt::Display for SourceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)?;
+        if let Some(function) = &self.function {
+            write!(f, " in {function}")?;
+        }
+        Ok(())
+    }
+}
+
+impl SourceMap {
+    /// Parse the content of the source map section.
+    pub fn from_section(data: &[u8]) -> Result<Self, GeneratorError> {
+        let invalid = || GeneratorError::InternalError("invalid source map section".to_owned());
+        let mut values = data
+            .chunks_exact(4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+
+        let version = values.next().ok_or_else(invalid)?;
+        if version != SOURCE_MAP_VERSION {
+            return Err(GeneratorError::InternalError(format!(
+                "unsupported source map version {version}"
+            )));
+        }
+        let count = values.next().ok_or_else(invalid)?;
+        let mut entries = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let mut next = || values.next().ok_or_else(invalid);
+            entries.push(SourceMapEntry {
+                func_index: next()?,
+                offset: next()?,
+                span: Span {
+                    start_line: next()?,
+                    start_column: next()?,
+                    end_line: next()?,
+                    end_column: next()?,
+                },
+            });
+        }
+        entries.sort_by_key(|entry| (entry.func_index, entry.offset));
+        Ok(SourceMap { entries })
+    }
+
+    /// Read the source map of a generated module, if it has one.
+    pub fn from_module(module: &mut Module) -> Result<Option<Self>, GeneratorError> {
+        module
+            .customs
+            .remove_raw(SOURCE_MAP_SECTION)
+            .map(|section| Self::from_section(&section.data))
+            .transpose()
+    }
+
+    /// Read the source map of the binary module `wasm`, if it has one.
+    pub fn from_wasm(wasm: &[u8]) -> Result<Option<Self>, GeneratorError> {
+        let mut module = Module::from_buffer(wasm)
+            .map_err(|e| GeneratorError::InternalError(format!("invalid module: {e}")))?;
+        Self::from_module(&mut module)
+    }
+
+    /// The span of the expression which generated the instruction at
+    /// `offset` in the function `func_index`, i.e. the span of the closest
+    /// mapped instruction at or before `offset`.
+    pub fn lookup(&self, func_index: u32, offset: u32) -> Option<&Span> {
+        let end = self
+            .entries
+            .partition_point(|entry| (entry.func_index, entry.offset) <= (func_index, offset));
+        self.entries[..end]
+            .last()
+            .filter(|entry| entry.func_index == func_index)
+            .map(|entry| &entry.span)
+    }
+
+    /// Locate the frames of `backtrace` in the Clarity source `file`,
+    /// innermost first. The frames which are not in a generated function,
+    /// like those of the standard library, are skipped.
+    #[cfg(feature = "developer-mode")]
+    pub fn frames(&self, backtrace: &wasmtime::WasmBacktrace, file: &str) -> Vec<SourceFrame> {
+        backtrace
+            .frames()
+            .iter()
+            .filter_map(|frame| {
+                let span = self.lookup(frame.func_index(), frame.func_offset()? as u32)?;
+                Some(SourceFrame {
+                    function: frame.func_name().map(str::to_string),
+                    file: file.to_string(),
+                    line: span.start_line,
+                    column: span.start_column,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clarity::types::StacksEpochId;
+    use clarity::vm::costs::LimitedCostTracker;
+    use clarity::vm::database::MemoryBackingStore;
+    use clarity::vm::types::QualifiedContractIdentifier;
+    use clarity::vm::ClarityVersion;
+
+    use super::SourceMap;
+    use crate::{compile_with_options, CompileOptions};
+
+    fn compile_snippet(snippet: &str, source_map: bool) -> Vec<u8> {
+        let mut datastore = MemoryBackingStore::new();
+        compile_with_options(
+            snippet,
+            &QualifiedContractIdentifier::transient(),
+            LimitedCostTracker::new_free(),
+            ClarityVersion::latest(),
+            StacksEpochId::latest(),
+            &mut datastore.as_analysis_db(),
+            &CompileOptions {
+                source_map,
+                ..CompileOptions::default()
+            },
+        )
+        .expect("Failed to compile contract.")
+        .module
+        .emit_wasm()
+    }
+
+    #[test]
+    fn source_map_round_trip() {
+        let wasm = compile_snippet(
+            "(define-read-only (get-value (n uint))
+  (+ n
+     (unwrap-panic (some u1))))",
+            true,
+        );
+        let source_map = SourceMap::from_wasm(&wasm)
+            .expect("Failed to read source map.")
+            .expect("Missing source map.");
+
+        assert!(!source_map.entries.is_empty());
+        let lines: Vec<_> = source_map
+            .entries
+            .iter()
+            .map(|entry| entry.span.start_line)
+            .collect();
+        assert!(lines.contains(&2));
+        assert!(lines.contains(&3));
+
+        let entry = &source_map.entries[source_map.entries.len() / 2];
+        assert_eq!(
+            source_map.lookup(entry.func_index, entry.offset),
+            Some(&entry.span)
+        );
+        assert_eq!(source_map.lookup(u32::MAX, 0), None);
+    }
+
+    #[cfg(feature = "developer-mode")]
+    #[test]
+    fn frames_locate_traps() {
+        use wasmtime::{Engine, Linker, Module, Store, Val, WasmBacktrace};
+
+        let wasm = compile_snippet(
+            "(define-read-only (get-value (value (optional uint)))
+  (+ u1
+     (unwrap-panic value)))",
+            true,
+        );
+        let source_map = SourceMap::from_wasm(&wasm)
+            .expect("Failed to read source map.")
+            .expect("Missing source map.");
+
+        let engine = Engine::default();
+        let module = Module::new(&engine, &wasm).expect("Failed to load module.");
+        let mut linker = Linker::new(&engine);
+        linker
+            .define_unknown_imports_as_traps(&module)
+            .expect("Failed to define imports.");
+        let mut store = Store::new(&engine, ());
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .expect("Failed to instantiate module.");
+
+        let get_value = instance
+            .get_func(&mut store, "get-value")
+            .expect("Missing get-value.");
+        let mut results = vec![Val::I32(0); get_value.ty(&store).results().len()];
+        // Call with `none`
+        let error = get_value
+            .call(
+                &mut store,
+                &[Val::I32(0), Val::I64(0), Val::I64(0)],
+                &mut results,
+            )
+            .expect_err("expected a trap");
+        let backtrace = error
+            .downcast_ref::<WasmBacktrace>()
+            .expect("Missing backtrace.");
+
+        let frames = source_map.frames(backtrace, "contract.clar");
+        let frame = frames.first().expect("Missing frame.");
+        assert_eq!(frame.file, "contract.clar");
+        assert_eq!((frame.line, frame.column), (3, 6));
+    }
+
+    #[test]
+    fn source_map_is_optional() {
+        let wasm = compile_snippet("(define-read-only (get-value) u1)", false);
+        assert!(SourceMap::from_wasm(&wasm)
+            .expect("Failed to read module.")
+            .is_none());
+    }
+}
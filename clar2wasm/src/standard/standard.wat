@@ -223,7 +223,8 @@
     (import "clarity" "block_height" (func $stdlib.block_height (result i64 i64)))
     (import "clarity" "burn_block_height" (func $stdlib.burn_block_height (result i64 i64)))
     (import "clarity" "stx_liquid_supply" (func $stdlib.stx_liquid_supply (result i64 i64)))
-    ;; TODO: these three funcs below could be hard-coded at compile-time.
+    ;; These three funcs are replaced by constants when the target network is
+    ;; given at compile time, see `WasmGenerator::with_network`.
     (import "clarity" "is_in_regtest" (func $stdlib.is_in_regtest (result i32)))
     (import "clarity" "is_in_mainnet" (func $stdlib.is_in_mainnet (result i32)))
     (import "clarity" "chain_id" (func $stdlib.chain_id (result i64 i64)))
@@ -264,12 +265,35 @@
 
     ;; sha512 K constants
     (data (i32.const 712) "\22\ae\28\d7\98\2f\8a\42\cd\65\ef\23\91\44\37\71\2f\3b\4d\ec\cf\fb\c0\b5\bc\db\89\81\a5\db\b5\e9\38\b5\48\f3\5b\c2\56\39\19\d0\05\b6\f1\11\f1\59\9b\4f\19\af\a4\82\3f\92\18\81\6d\da\d5\5e\1c\ab\42\02\03\a3\98\aa\07\d8\be\6f\70\45\01\5b\83\12\8c\b2\e4\4e\be\85\31\24\e2\b4\ff\d5\c3\7d\0c\55\6f\89\7b\f2\74\5d\be\72\b1\96\16\3b\fe\b1\de\80\35\12\c7\25\a7\06\dc\9b\94\26\69\cf\74\f1\9b\c1\d2\4a\f1\9e\c1\69\9b\e4\e3\25\4f\38\86\47\be\ef\b5\d5\8c\8b\c6\9d\c1\0f\65\9c\ac\77\cc\a1\0c\24\75\02\2b\59\6f\2c\e9\2d\83\e4\a6\6e\aa\84\74\4a\d4\fb\41\bd\dc\a9\b0\5c\b5\53\11\83\da\88\f9\76\ab\df\66\ee\52\51\3e\98\10\32\b4\2d\6d\c6\31\a8\3f\21\fb\98\c8\27\03\b0\e4\0e\ef\be\c7\7f\59\bf\c2\8f\a8\3d\f3\0b\e0\c6\25\a7\0a\93\47\91\a7\d5\6f\82\03\e0\51\63\ca\06\70\6e\0e\0a\67\29\29\14\fc\2f\d2\46\85\0a\b7\27\26\c9\26\5c\38\21\1b\2e\ed\2a\c4\5a\fc\6d\2c\4d\df\b3\95\9d\13\0d\38\53\de\63\af\8b\54\73\0a\65\a8\b2\77\3c\bb\0a\6a\76\e6\ae\ed\47\2e\c9\c2\81\3b\35\82\14\85\2c\72\92\64\03\f1\4c\a1\e8\bf\a2\01\30\42\bc\4b\66\1a\a8\91\97\f8\d0\70\8b\4b\c2\30\be\54\06\a3\51\6c\c7\18\52\ef\d6\19\e8\92\d1\10\a9\65\55\24\06\99\d6\2a\20\71\57\85\35\0e\f4\b8\d1\bb\32\70\a0\6a\10\c8\d0\d2\b8\16\c1\a4\19\53\ab\41\51\08\6c\37\1e\99\eb\8e\df\4c\77\48\27\a8\48\9b\e1\b5\bc\b0\34\63\5a\c9\c5\b3\0c\1c\39\cb\8a\41\e3\4a\aa\d8\4e\73\e3\63\77\4f\ca\9c\5b\a3\b8\b2\d6\f3\6f\2e\68\fc\b2\ef\5d\ee\82\8f\74\60\2f\17\43\6f\63\a5\78\72\ab\f0\a1\14\78\c8\84\ec\39\64\1a\08\02\c7\8c\28\1e\63\23\fa\ff\be\90\e9\bd\82\de\eb\6c\50\a4\15\79\c6\b2\f7\a3\f9\be\2b\53\72\e3\f2\78\71\c6\9c\61\26\ea\ce\3e\27\ca\07\c2\c0\21\c7\b8\86\d1\1e\eb\e0\cd\d6\7d\da\ea\78\d1\6e\ee\7f\4f\7d\f5\ba\6f\17\72\aa\67\f0\06\a6\98\c8\a2\c5\7d\63\0a\ae\0d\f9\be\04\98\3f\11\1b\47\1c\13\35\0b\71\1b\84\7d\04\23\f5\77\db\28\93\24\c7\40\7b\ab\ca\32\bc\be\c9\15\0a\be\9e\3c\4c\0d\10\9c\c4\67\1d\43\b6\42\3e\cb\be\d4\c5\4c\2a\7e\65\fc\9c\29\7f\59\ec\fa\d6\3a\ab\6f\cb\5f\17\58\47\4a\8c\19\44\6c")
+
+    ;; Report of the last runtime error (48 bytes), printed by
+    ;; `$stdlib.runtime-error`. It is the serialization of a buffer: the type
+    ;; prefix and length, the magic "clar2wasm-runtime-error", then the code
+    ;; and the location of the error, as little-endian 32-bit integers filled
+    ;; in at 1380.
+    (data (i32.const 1352) "\02\00\00\00\2b" "clar2wasm-runtime-error"
+        "\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00")
     
     ;; table that contains the 5 hash160 functions used during compression
     (type $hash160-compress-function (func (param i32 i32 i32 i32) (result i32)))
     (table 5 funcref) ;; table for hash160 compress function
     (elem (i32.const 0) $hash160-f1 $hash160-f2 $hash160-f3 $hash160-f4 $hash160-f5)
 
+    ;; Details of the last runtime error, exported so that the host can read
+    ;; them after the trap and report the matching error. The code is -1 until
+    ;; a runtime error occurs. The location is the span of the Clarity
+    ;; expression which raised the error, when known, or all 0 otherwise.
+    (global $runtime-error-code (mut i32) (i32.const -1))
+    (global $runtime-error-start-line (mut i32) (i32.const 0))
+    (global $runtime-error-start-column (mut i32) (i32.const 0))
+    (global $runtime-error-end-line (mut i32) (i32.const 0))
+    (global $runtime-error-end-column (mut i32) (i32.const 0))
+    (export "runtime-error-code" (global $runtime-error-code))
+    (export "runtime-error-start-line" (global $runtime-error-start-line))
+    (export "runtime-error-start-column" (global $runtime-error-start-column))
+    (export "runtime-error-end-line" (global $runtime-error-end-line))
+    (export "runtime-error-end-column" (global $runtime-error-end-column))
+
     ;; The error code is one of:
         ;; 0: overflow
         ;; 1: underflow
@@ -279,11 +303,46 @@
         ;; 5: buffer to integer expects a buffer length <= 16
         ;; 6: panic
         ;; 7: short return
+        ;; 8: memory limit exceeded
     (func $stdlib.runtime-error (param $error-code i32)
-        ;; TODO: Implement runtime error
+        (global.set $runtime-error-code (local.get $error-code))
+        ;; The clarity host drops the instance when it traps, so the error is
+        ;; also printed for the hosts which cannot read the globals.
+        (i32.store (i32.const 1380) (local.get $error-code))
+        (i32.store (i32.const 1384) (global.get $runtime-error-start-line))
+        (i32.store (i32.const 1388) (global.get $runtime-error-start-column))
+        (i32.store (i32.const 1392) (global.get $runtime-error-end-line))
+        (i32.store (i32.const 1396) (global.get $runtime-error-end-column))
+        (call $stdlib.print (i32.const 1352) (i32.const 48))
         unreachable
     )
 
+    ;; Grows the memory so that it covers the addresses below `$end`. Raises
+    ;; error 8 if the memory cannot grow that much, because of its maximum or
+    ;; because the host refuses it.
+    (func $stdlib.ensure-memory (param $end i32)
+        (local $pages i32)
+        ;; Number of missing pages, rounding `$end` up to the next page
+        (local.set $pages
+            (i32.sub
+                (i32.wrap_i64
+                    (i64.shr_u
+                        (i64.add (i64.extend_i32_u (local.get $end)) (i64.const 0xffff))
+                        (i64.const 16)
+                    )
+                )
+                (memory.size)
+            )
+        )
+        (if (i32.gt_s (local.get $pages) (i32.const 0))
+            (then
+                (if (i32.eq (memory.grow (local.get $pages)) (i32.const -1))
+                    (then (call $stdlib.runtime-error (i32.const 8)))
+                )
+            )
+        )
+    )
+
     ;; This function can be used to add either signed or unsigned integers
     (func $stdlib.add-int128 (param $a_lo i64) (param $a_hi i64) (param $b_lo i64) (param $b_hi i64) (result i64 i64)
         ;; Add the lower 64 bits
@@ -1492,7 +1551,12 @@
 
     (func $extend-data (param $offset i32) (param $length i32) (result i32)
         (local $res_len i32) (local $len64 i64)
-        ;; TODO: check if enough pages of memory and grow accordingly
+        ;; The extended data starts 288 bytes after the stack pointer, and takes
+        ;; at most 72 more bytes than the data: 8 for the size and 64 for the
+        ;; padding
+        (call $stdlib.ensure-memory
+            (i32.add (global.get $stack-pointer) (i32.add (local.get $length) (i32.const 360)))
+        )
 
         ;; Move data to the working stack, so that it has this relative configuration:
         ;;   0..32 -> Initial hash vals (will be the result hash in the end)
@@ -2858,6 +2922,13 @@
         ;; Message length in 8 bytes
         (local $message_length_64 i64)
 
+        ;; The padded data starts 704 bytes after the stack pointer, and takes at
+        ;; most 144 more bytes than the data: 16 for the size and 128 for the
+        ;; padding
+        (call $stdlib.ensure-memory
+            (i32.add (global.get $stack-pointer) (i32.add (local.get $length) (i32.const 848)))
+        )
+
         ;; Copying initial values (64 bytes) for SHA-512 from 648 index
         (memory.copy (global.get $stack-pointer) (i32.const 648) (i32.const 64))
 
@@ -3487,4 +3558,5 @@
     (export "stdlib.convert-scalars-to-utf8" (func $stdlib.convert-scalars-to-utf8))
     (export "stdlib.is-valid-string-ascii" (func $stdlib.is-valid-string-ascii))
     (export "stdlib.utf8-to-string-utf8" (func $stdlib.utf8-to-string-utf8))
+    (export "stdlib.ensure-memory" (func $stdlib.ensure-memory))
 )
@@ -5,22 +5,107 @@
 #![allow(clippy::expect_used, clippy::unwrap_used)]
 
 use std::collections::HashMap;
+use std::fmt;
 
 use clarity::consts::CHAIN_ID_TESTNET;
 use clarity::types::StacksEpochId;
+use clarity::util::hash::Hash160;
 use clarity::vm::analysis::run_analysis;
 use clarity::vm::ast::build_ast;
-use clarity::vm::clarity_wasm::initialize_contract;
-use clarity::vm::contexts::GlobalContext;
+use clarity::vm::callables::DefineType;
+use clarity::vm::clarity_wasm::{call_function, initialize_contract};
+use clarity::vm::contexts::{CallStack, GlobalContext};
 use clarity::vm::contracts::Contract;
 use clarity::vm::costs::LimitedCostTracker;
 use clarity::vm::database::ClarityDatabase;
-use clarity::vm::errors::{CheckErrors, Error, WasmError};
+use clarity::vm::errors::{CheckErrors, Error, InterpreterError, RuntimeErrorType, WasmError};
+use clarity::vm::events::StacksTransactionEvent;
 use clarity::vm::types::{PrincipalData, QualifiedContractIdentifier, StandardPrincipalData};
 use clarity::vm::{eval_all, ClarityVersion, ContractContext, Value};
 
-use crate::compile;
-use crate::datastore::{BurnDatastore, Datastore, StacksConstants};
+use crate::coverage::{parse_counters, ContractCoverage};
+use crate::datastore::{
+    BlockInfo, BlockSettings, BurnDatastore, Datastore, DatastoreDiff, StacksConstants,
+};
+use crate::wasm_generator::RuntimeErrorReport;
+use crate::{compile_with_options, CompileOptions};
+
+/// The result of a contract function call.
+#[derive(Debug, Clone)]
+pub struct CallResult {
+    /// The value returned by the function.
+    pub value: Value,
+    /// The events emitted by the call, if it was committed.
+    pub events: Vec<StacksTransactionEvent>,
+    /// Whether the changes made by the call were committed, or rolled back.
+    pub committed: bool,
+}
+
+/// The accounts given a STX balance when a `TestEnvironment` is created.
+#[derive(Debug, Clone)]
+pub struct Genesis {
+    pub accounts: Vec<(PrincipalData, u128)>,
+}
+
+impl Default for Genesis {
+    /// Only the transient principal, which deploys the contracts, is funded.
+    fn default() -> Self {
+        Genesis {
+            accounts: vec![(
+                PrincipalData::Standard(StandardPrincipalData::transient()),
+                1_000_000_000,
+            )],
+        }
+    }
+}
+
+impl Genesis {
+    /// The default genesis, plus the test wallets `wallet_1` to
+    /// `wallet_<count>`, each funded with `balance`.
+    pub fn with_wallets(count: usize, balance: u128) -> Self {
+        let mut genesis = Genesis::default();
+        genesis
+            .accounts
+            .extend((1..=count).map(|i| (test_wallet(&format!("wallet_{i}")), balance)));
+        genesis
+    }
+}
+
+/// The deterministic testnet address of the test wallet `name`.
+pub fn test_wallet(name: &str) -> PrincipalData {
+    // Testnet single-sig address version
+    let version = 26;
+    PrincipalData::Standard(StandardPrincipalData(
+        version,
+        Hash160::from_data(name.as_bytes()).0,
+    ))
+}
+
+/// The changes to the state of a `TestEnvironment` since a checkpoint.
+#[derive(Debug, Clone)]
+pub struct StateDiff {
+    /// The changes to the data visible from the current chain tip.
+    pub data: DatastoreDiff,
+    /// The changes to the contract metadata, with keys of the form
+    /// `<contract>::<key>`.
+    pub metadata: DatastoreDiff,
+    /// The names of the contracts deployed since the checkpoint.
+    pub new_contracts: Vec<String>,
+    /// The chain height at the checkpoint and now.
+    pub chain_height: (u32, u32),
+}
+
+/// The state of a `TestEnvironment` saved by `checkpoint`.
+#[derive(Clone)]
+struct Checkpoint {
+    contract_contexts: HashMap<String, ContractContext>,
+    datastore: Datastore,
+    burn_datastore: BurnDatastore,
+    cost_tracker: LimitedCostTracker,
+    events: Vec<StacksTransactionEvent>,
+    coverage: Option<HashMap<String, ContractCoverage>>,
+    last_runtime_error: Option<RuntimeErrorReport>,
+}
 
 #[derive(Clone)]
 pub struct TestEnvironment {
@@ -30,10 +115,27 @@ pub struct TestEnvironment {
     datastore: Datastore,
     burn_datastore: BurnDatastore,
     cost_tracker: LimitedCostTracker,
+    checkpoints: HashMap<String, Checkpoint>,
+    /// The events emitted by the committed deployments and calls.
+    events: Vec<StacksTransactionEvent>,
+    /// The coverage of the contracts, by name, if enabled.
+    coverage: Option<HashMap<String, ContractCoverage>>,
+    /// The runtime error reported by the last failed deployment or call, if
+    /// it was raised by a compiled contract.
+    last_runtime_error: Option<RuntimeErrorReport>,
 }
 
 impl TestEnvironment {
     pub fn new(epoch: StacksEpochId, version: ClarityVersion) -> Self {
+        Self::new_with_genesis(epoch, version, &Genesis::default())
+    }
+
+    /// Create an environment in which the accounts of `genesis` are funded.
+    pub fn new_with_genesis(
+        epoch: StacksEpochId,
+        version: ClarityVersion,
+        genesis: &Genesis,
+    ) -> Self {
         let constants = StacksConstants::default();
         let burn_datastore = BurnDatastore::new(constants.clone());
         let mut datastore = Datastore::new();
@@ -45,25 +147,143 @@ impl TestEnvironment {
             .expect("Failed to set epoch version.");
         db.commit().expect("Failed to commit.");
 
-        // Give one account a starting balance, to be used for testing.
-        let recipient = PrincipalData::Standard(StandardPrincipalData::transient());
-        let amount = 1_000_000_000;
-        let mut conn = ClarityDatabase::new(&mut datastore, &burn_datastore, &burn_datastore);
+        let mut env = Self {
+            contract_contexts: HashMap::new(),
+            epoch,
+            version,
+            datastore,
+            burn_datastore,
+            cost_tracker,
+            checkpoints: HashMap::new(),
+            events: Vec::new(),
+            coverage: None,
+            last_runtime_error: None,
+        };
+        for (principal, amount) in &genesis.accounts {
+            env.mint_stx(principal, *amount);
+        }
+        env
+    }
+
+    /// Credit `amount` µSTX to `principal`, increasing the liquid supply.
+    pub fn mint_stx(&mut self, principal: &PrincipalData, amount: u128) {
+        let mut conn = ClarityDatabase::new(
+            &mut self.datastore,
+            &self.burn_datastore,
+            &self.burn_datastore,
+        );
         execute(&mut conn, |database| {
-            let mut snapshot = database.get_stx_balance_snapshot(&recipient)?;
+            let mut snapshot = database.get_stx_balance_snapshot(principal)?;
             snapshot.credit(amount)?;
             snapshot.save()?;
             database.increment_ustx_liquid_supply(amount)
         })
         .expect("Failed to increment liquid supply.");
+    }
 
-        Self {
-            contract_contexts: HashMap::new(),
-            epoch,
-            version,
-            datastore,
-            burn_datastore,
-            cost_tracker,
+    /// Set the STX balance of `principal` to `amount` µSTX, adjusting the
+    /// liquid supply.
+    pub fn set_stx_balance(&mut self, principal: &PrincipalData, amount: u128) {
+        let mut conn = ClarityDatabase::new(
+            &mut self.datastore,
+            &self.burn_datastore,
+            &self.burn_datastore,
+        );
+        execute(&mut conn, |database| {
+            let mut snapshot = database.get_stx_balance_snapshot(principal)?;
+            let balance = snapshot.get_available_balance()?;
+            if amount >= balance {
+                snapshot.credit(amount - balance)?;
+                snapshot.save()?;
+                database.increment_ustx_liquid_supply(amount - balance)
+            } else {
+                snapshot.debit(balance - amount)?;
+                snapshot.save()?;
+                database.decrement_ustx_liquid_supply(balance - amount)
+            }
+        })
+        .expect("Failed to set STX balance.");
+    }
+
+    pub fn get_stx_balance(&mut self, principal: &PrincipalData) -> u128 {
+        let mut conn = ClarityDatabase::new(
+            &mut self.datastore,
+            &self.burn_datastore,
+            &self.burn_datastore,
+        );
+        execute(&mut conn, |database| {
+            database
+                .get_stx_balance_snapshot(principal)?
+                .get_available_balance()
+        })
+        .expect("Failed to get STX balance.")
+    }
+
+    /// The balance of `principal` in the fungible token `token_name` defined
+    /// by the contract named `contract_name`.
+    pub fn get_ft_balance(
+        &mut self,
+        contract_name: &str,
+        token_name: &str,
+        principal: &PrincipalData,
+    ) -> Result<u128, Error> {
+        let contract_context = self
+            .contract_contexts
+            .get(contract_name)
+            .ok_or_else(|| CheckErrors::NoSuchContract(contract_name.to_string()))?;
+        let descriptor = contract_context
+            .meta_ft
+            .iter()
+            .find(|(name, _)| name.as_str() == token_name)
+            .map(|(_, descriptor)| descriptor)
+            .ok_or_else(|| CheckErrors::NoSuchFT(token_name.to_string()))?;
+        let contract_id = contract_context.contract_identifier.clone();
+        let descriptor = descriptor.clone();
+
+        let mut conn = ClarityDatabase::new(
+            &mut self.datastore,
+            &self.burn_datastore,
+            &self.burn_datastore,
+        );
+        execute(&mut conn, |database| {
+            database.get_ft_balance(&contract_id, token_name, principal, Some(&descriptor))
+        })
+    }
+
+    /// The owner of the asset `identifier` of the non-fungible token
+    /// `token_name` defined by the contract named `contract_name`, if it
+    /// was minted.
+    pub fn get_nft_owner(
+        &mut self,
+        contract_name: &str,
+        token_name: &str,
+        identifier: &Value,
+    ) -> Result<Option<PrincipalData>, Error> {
+        let contract_context = self
+            .contract_contexts
+            .get(contract_name)
+            .ok_or_else(|| CheckErrors::NoSuchContract(contract_name.to_string()))?;
+        let key_type = contract_context
+            .meta_nft
+            .iter()
+            .find(|(name, _)| name.as_str() == token_name)
+            .map(|(_, descriptor)| descriptor)
+            .ok_or_else(|| CheckErrors::NoSuchNFT(token_name.to_string()))?
+            .key_type
+            .clone();
+        let contract_id = contract_context.contract_identifier.clone();
+
+        let mut conn = ClarityDatabase::new(
+            &mut self.datastore,
+            &self.burn_datastore,
+            &self.burn_datastore,
+        );
+        match execute(&mut conn, |database| {
+            database.get_nft_owner(&contract_id, token_name, identifier, &key_type)
+        }) {
+            Ok(owner) => Ok(Some(owner)),
+            Err(Error::Runtime(RuntimeErrorType::NoSuchToken, _)) => Ok(None),
+            Err(error) => Err(error),
         }
     }
 
@@ -76,18 +296,31 @@ impl TestEnvironment {
             StandardPrincipalData::transient(),
             (*contract_name).into(),
         );
+        self.init_contract_with_id(contract_id, snippet)
+    }
 
+    /// Deploy the contract `snippet` as `contract_id`. The contract can then
+    /// be referred to by its name only.
+    pub fn init_contract_with_id(
+        &mut self,
+        contract_id: QualifiedContractIdentifier,
+        snippet: &str,
+    ) -> Result<Option<Value>, Error> {
         let mut compile_result = self
             .datastore
             .as_analysis_db()
             .execute(|analysis_db| {
-                compile(
+                compile_with_options(
                     snippet,
                     &contract_id,
                     LimitedCostTracker::new_free(),
                     self.version,
                     self.epoch,
                     analysis_db,
+                    &CompileOptions {
+                        coverage: self.coverage.is_some(),
+                        ..CompileOptions::default()
+                    },
                 )
                 .map_err(|_| CheckErrors::Expects("Compilation failure".to_string()))
             })
@@ -112,6 +345,10 @@ impl TestEnvironment {
             &self.burn_datastore,
             &self.burn_datastore,
         );
+        if let (Some(coverage), Some(map)) = (&mut self.coverage, compile_result.coverage.take()) {
+            coverage.insert(contract_id.name.to_string(), ContractCoverage::new(map));
+        }
+
         let mut global_context =
             GlobalContext::new(false, CHAIN_ID_TESTNET, conn, cost_tracker, self.epoch);
         global_context.begin();
@@ -119,12 +356,19 @@ impl TestEnvironment {
             .execute(|g| g.database.insert_contract_hash(&contract_id, snippet))
             .expect("Failed to insert contract hash.");
 
-        let return_val = initialize_contract(
+        self.last_runtime_error = None;
+        let return_val = match initialize_contract(
             &mut global_context,
             &mut contract_context,
             None,
             &compile_result.contract_analysis,
-        )?;
+        ) {
+            Ok(return_val) => return_val,
+            Err(error) => {
+                self.last_runtime_error = take_runtime_error(&global_context);
+                return Err(map_runtime_error(error, self.last_runtime_error.as_ref()));
+            }
+        };
 
         let data_size = contract_context.data_size;
         global_context.database.insert_contract(
@@ -138,15 +382,191 @@ impl TestEnvironment {
             .set_contract_data_size(&contract_id, data_size)
             .expect("Failed to set contract data size.");
 
+        if let Some(batch) = global_context.event_batches.last() {
+            let events = take_coverage(&mut self.coverage, batch.events.clone());
+            self.events.extend(events);
+        }
         global_context.commit().unwrap();
         self.cost_tracker = global_context.cost_track;
 
         self.contract_contexts
-            .insert(contract_name.to_string(), contract_context);
+            .insert(contract_id.name.to_string(), contract_context);
 
         Ok(return_val)
     }
 
+    /// Call the public function `function_name` in the contract named
+    /// `contract_name` with `args`, as `sender`, optionally sponsored by
+    /// `sponsor`. If the function returns an `err` response, the call is
+    /// rolled back, like in a transaction.
+    pub fn call_public(
+        &mut self,
+        contract_name: &str,
+        function_name: &str,
+        args: &[Value],
+        sender: PrincipalData,
+        sponsor: Option<PrincipalData>,
+    ) -> Result<CallResult, Error> {
+        self.call_function_of_type(
+            Some(DefineType::Public),
+            contract_name,
+            function_name,
+            args,
+            sender,
+            sponsor,
+        )
+    }
+
+    /// Call the read-only function `function_name` in the contract named
+    /// `contract_name` with `args`, as `sender`, optionally sponsored by
+    /// `sponsor`.
+    pub fn call_read_only(
+        &mut self,
+        contract_name: &str,
+        function_name: &str,
+        args: &[Value],
+        sender: PrincipalData,
+        sponsor: Option<PrincipalData>,
+    ) -> Result<CallResult, Error> {
+        self.call_function_of_type(
+            Some(DefineType::ReadOnly),
+            contract_name,
+            function_name,
+            args,
+            sender,
+            sponsor,
+        )
+    }
+
+    /// Call the private function `function_name` in the contract named
+    /// `contract_name` with `args`, as `sender`, optionally sponsored by
+    /// `sponsor`. Private functions cannot be called from outside of their
+    /// contract on chain, this is intended to test them directly.
+    pub fn call_private(
+        &mut self,
+        contract_name: &str,
+        function_name: &str,
+        args: &[Value],
+        sender: PrincipalData,
+        sponsor: Option<PrincipalData>,
+    ) -> Result<CallResult, Error> {
+        self.call_function_of_type(
+            Some(DefineType::Private),
+            contract_name,
+            function_name,
+            args,
+            sender,
+            sponsor,
+        )
+    }
+
+    /// Call `function_name` in the contract named `contract_name` with
+    /// `args`, as `sender`, optionally sponsored by `sponsor`, whatever its
+    /// type. A public function returning an `err` response is rolled back,
+    /// like in a transaction, and emits no events.
+    pub fn call_contract_function(
+        &mut self,
+        contract_name: &str,
+        function_name: &str,
+        args: &[Value],
+        sender: PrincipalData,
+        sponsor: Option<PrincipalData>,
+    ) -> Result<CallResult, Error> {
+        self.call_function_of_type(None, contract_name, function_name, args, sender, sponsor)
+    }
+
+    fn call_function_of_type(
+        &mut self,
+        expected_type: Option<DefineType>,
+        contract_name: &str,
+        function_name: &str,
+        args: &[Value],
+        sender: PrincipalData,
+        sponsor: Option<PrincipalData>,
+    ) -> Result<CallResult, Error> {
+        let contract_context = self
+            .contract_contexts
+            .get(contract_name)
+            .ok_or_else(|| CheckErrors::NoSuchContract(contract_name.to_string()))?
+            .clone();
+        let define_type = contract_context
+            .lookup_function(function_name)
+            .map(|function| function.define_type)
+            .ok_or_else(|| {
+                CheckErrors::NoSuchPublicFunction(
+                    contract_name.to_string(),
+                    function_name.to_string(),
+                )
+            })?;
+        if let Some(expected) = expected_type.filter(|expected| *expected != define_type) {
+            return Err(CheckErrors::Expects(format!(
+                "expected a {} function, but {contract_name}.{function_name} is {}",
+                describe_define_type(expected),
+                describe_define_type(define_type),
+            ))
+            .into());
+        }
+
+        let mut cost_tracker = LimitedCostTracker::new_free();
+        std::mem::swap(&mut self.cost_tracker, &mut cost_tracker);
+
+        let conn = ClarityDatabase::new(
+            &mut self.datastore,
+            &self.burn_datastore,
+            &self.burn_datastore,
+        );
+        let mut global_context =
+            GlobalContext::new(false, CHAIN_ID_TESTNET, conn, cost_tracker, self.epoch);
+        global_context.begin();
+
+        self.last_runtime_error = None;
+        let mut call_stack = CallStack::new();
+        let result = call_function(
+            function_name,
+            args,
+            &mut global_context,
+            &contract_context,
+            &mut call_stack,
+            Some(sender.clone()),
+            Some(sender),
+            sponsor,
+        );
+
+        let committed = match &result {
+            Ok(Value::Response(response)) if define_type == DefineType::Public => {
+                response.committed
+            }
+            Ok(_) => true,
+            Err(_) => {
+                self.last_runtime_error = take_runtime_error(&global_context);
+                false
+            }
+        };
+        // The coverage is reported with events, which are also collected
+        // from the calls which are rolled back.
+        let events = global_context
+            .event_batches
+            .last()
+            .map(|batch| take_coverage(&mut self.coverage, batch.events.clone()))
+            .unwrap_or_default();
+        let events = if committed {
+            global_context.commit()?;
+            self.events.extend(events.iter().cloned());
+            events
+        } else {
+            global_context.roll_back()?;
+            Vec::new()
+        };
+        self.cost_tracker = global_context.cost_track;
+
+        Ok(CallResult {
+            value: result
+                .map_err(|error| map_runtime_error(error, self.last_runtime_error.as_ref()))?,
+            events,
+            committed,
+        })
+    }
+
     pub fn evaluate(&mut self, snippet: &str) -> Result<Option<Value>, Error> {
         self.init_contract_with_snippet("snippet", snippet)
     }
@@ -155,9 +575,127 @@ impl TestEnvironment {
         self.contract_contexts.get(contract_name)
     }
 
+    /// The events emitted by the committed deployments and calls, in order.
+    pub fn events(&self) -> &[StacksTransactionEvent] {
+        &self.events
+    }
+
+    /// The code and location of the runtime error which made the last
+    /// deployment or call fail, if it failed with one.
+    pub fn last_runtime_error(&self) -> Option<&RuntimeErrorReport> {
+        self.last_runtime_error.as_ref()
+    }
+
+    /// Instrument the contracts deployed from now on to count the executions
+    /// of their expressions, see `coverage_lcov`.
+    pub fn enable_coverage(&mut self) {
+        self.coverage.get_or_insert_with(HashMap::new);
+    }
+
+    /// The coverage of the contract named `contract_name`, if it was
+    /// deployed with the coverage enabled.
+    pub fn get_coverage(&self, contract_name: &str) -> Option<&ContractCoverage> {
+        self.coverage.as_ref()?.get(contract_name)
+    }
+
+    /// An LCOV report of the coverage of all the instrumented contracts,
+    /// with the path of the source of each contract given by `source_path`.
+    pub fn coverage_lcov(&self, source_path: impl Fn(&str) -> String) -> String {
+        let mut contracts: Vec<_> = self.coverage.iter().flatten().collect();
+        contracts.sort_by_key(|(name, _)| name.as_str());
+        contracts
+            .into_iter()
+            .map(|(name, coverage)| coverage.lcov(&source_path(name)))
+            .collect()
+    }
+
     pub fn advance_chain_tip(&mut self, count: u32) -> u32 {
         self.burn_datastore.advance_chain_tip(count);
-        self.datastore.advance_chain_tip(count)
+        self.datastore
+            .advance_chain_tip(count)
+            .expect("Failed to advance the chain tip.")
+    }
+
+    /// Set the settings used to generate the info of the next blocks, like
+    /// their time spacing, miner and PoX payout addresses.
+    pub fn set_block_settings(&mut self, settings: BlockSettings) {
+        self.burn_datastore.set_block_settings(settings);
+    }
+
+    pub fn get_block_info(&self, height: u32) -> Option<&BlockInfo> {
+        self.burn_datastore.get_block_info(height)
+    }
+
+    /// Set the info of the block at `height`, either an existing block, or
+    /// one which will be created by `advance_chain_tip`. The burn block
+    /// height of the block is always `height`.
+    pub fn set_block_info(&mut self, height: u32, block_info: BlockInfo) {
+        self.burn_datastore.set_block_info(height, block_info);
+    }
+
+    /// Save the current state of the environment as `name`, replacing any
+    /// previous checkpoint with this name. A checkpoint is a full copy of
+    /// the state, so its cost grows with the data of the deployed contracts
+    /// and with the number of blocks.
+    pub fn checkpoint(&mut self, name: &str) {
+        self.checkpoints.insert(
+            name.to_string(),
+            Checkpoint {
+                contract_contexts: self.contract_contexts.clone(),
+                datastore: self.datastore.clone(),
+                burn_datastore: self.burn_datastore.clone(),
+                cost_tracker: self.cost_tracker.clone(),
+                events: self.events.clone(),
+                coverage: self.coverage.clone(),
+                last_runtime_error: self.last_runtime_error.clone(),
+            },
+        );
+    }
+
+    fn get_checkpoint(&self, name: &str) -> Result<&Checkpoint, Error> {
+        self.checkpoints.get(name).ok_or_else(|| {
+            Error::Interpreter(InterpreterError::Expect(format!(
+                "No checkpoint named {name}"
+            )))
+        })
+    }
+
+    /// Restore the state saved by `checkpoint(name)`. The checkpoint is kept,
+    /// so that it can be restored again. The SQLite file of the datastore,
+    /// if any, is rewritten to match the restored state.
+    pub fn restore(&mut self, name: &str) -> Result<(), Error> {
+        let checkpoint = self.get_checkpoint(name)?.clone();
+        self.datastore.restore(&checkpoint.datastore)?;
+        self.contract_contexts = checkpoint.contract_contexts;
+        self.burn_datastore = checkpoint.burn_datastore;
+        self.cost_tracker = checkpoint.cost_tracker;
+        self.events = checkpoint.events;
+        self.coverage = checkpoint.coverage;
+        self.last_runtime_error = checkpoint.last_runtime_error;
+        Ok(())
+    }
+
+    /// The changes to the state of the environment since `checkpoint(name)`.
+    pub fn diff(&self, name: &str) -> Result<StateDiff, Error> {
+        let checkpoint = self.get_checkpoint(name)?;
+
+        let mut new_contracts: Vec<_> = self
+            .contract_contexts
+            .keys()
+            .filter(|name| !checkpoint.contract_contexts.contains_key(*name))
+            .cloned()
+            .collect();
+        new_contracts.sort();
+
+        Ok(StateDiff {
+            data: self.datastore.diff(&checkpoint.datastore),
+            metadata: self.datastore.diff_metadata(&checkpoint.datastore),
+            new_contracts,
+            chain_height: (
+                checkpoint.datastore.get_chain_height(),
+                self.datastore.get_chain_height(),
+            ),
+        })
     }
 
     pub fn interpret_contract_with_snippet(
@@ -199,23 +737,12 @@ impl TestEnvironment {
 
         let mut contract_context = ContractContext::new(contract_id.clone(), self.version);
 
-        let mut conn = ClarityDatabase::new(
+        let conn = ClarityDatabase::new(
             &mut self.datastore,
             &self.burn_datastore,
             &self.burn_datastore,
         );
 
-        // Give one account a starting balance, to be used for testing.
-        let recipient = PrincipalData::Standard(StandardPrincipalData::transient());
-        let amount = 1_000_000_000;
-        execute(&mut conn, |database| {
-            let mut snapshot = database.get_stx_balance_snapshot(&recipient)?;
-            snapshot.credit(amount)?;
-            snapshot.save()?;
-            database.increment_ustx_liquid_supply(amount)
-        })
-        .expect("Failed to increment liquid supply.");
-
         let mut global_context = GlobalContext::new(
             false,
             CHAIN_ID_TESTNET,
@@ -229,12 +756,20 @@ impl TestEnvironment {
             .execute(|g| g.database.insert_contract_hash(&contract_id, snippet))
             .expect("Failed to insert contract hash.");
 
-        eval_all(
+        let return_val = eval_all(
             &contract_analysis.expressions,
             &mut contract_context,
             &mut global_context,
             None,
-        )
+        )?;
+
+        if let Some(batch) = global_context.event_batches.last() {
+            self.events.extend(batch.events.iter().cloned());
+        }
+        global_context.commit()?;
+        self.cost_tracker = global_context.cost_track;
+
+        Ok(return_val)
     }
 
     pub fn interpret(&mut self, snippet: &str) -> Result<Option<Value>, Error> {
@@ -248,6 +783,75 @@ impl Default for TestEnvironment {
     }
 }
 
+/// Remove the coverage reports from `events`, adding their counters to the
+/// coverage of the contracts which printed them.
+fn take_coverage(
+    coverage: &mut Option<HashMap<String, ContractCoverage>>,
+    events: Vec<StacksTransactionEvent>,
+) -> Vec<StacksTransactionEvent> {
+    let Some(coverage) = coverage else {
+        return events;
+    };
+    events
+        .into_iter()
+        .filter(|event| match event {
+            StacksTransactionEvent::SmartContractEvent(data) => {
+                match (
+                    parse_counters(&data.value),
+                    coverage.get_mut(data.key.0.name.as_str()),
+                ) {
+                    (Some(counters), Some(contract)) => {
+                        contract.add(&counters);
+                        false
+                    }
+                    _ => true,
+                }
+            }
+            _ => true,
+        })
+        .collect()
+}
+
+/// The runtime error printed by a compiled contract before it trapped, in
+/// the events of `global_context`, if any. The events of the failed
+/// deployment or call are not rolled back yet.
+fn take_runtime_error(global_context: &GlobalContext) -> Option<RuntimeErrorReport> {
+    global_context
+        .event_batches
+        .iter()
+        .rev()
+        .flat_map(|batch| batch.events.iter().rev())
+        .find_map(|event| match event {
+            StacksTransactionEvent::SmartContractEvent(data) => {
+                RuntimeErrorReport::parse(&data.value)
+            }
+            _ => None,
+        })
+}
+
+fn describe_define_type(define_type: DefineType) -> &'static str {
+    match define_type {
+        DefineType::Public => "public",
+        DefineType::ReadOnly => "read-only",
+        DefineType::Private => "private",
+    }
+}
+
+/// Map the trap of a compiled contract, which reported `report` before
+/// trapping, to the runtime error which the interpreter raises in the same
+/// case, if any.
+fn map_runtime_error(error: Error, report: Option<&RuntimeErrorReport>) -> Error {
+    match (
+        &error,
+        report.and_then(|report| report.code.runtime_error()),
+    ) {
+        (Error::Wasm(WasmError::Runtime(_)), Some(runtime_error)) => {
+            Error::Runtime(runtime_error, None)
+        }
+        _ => error,
+    }
+}
+
 pub fn execute<F, T, E>(conn: &mut ClarityDatabase, f: F) -> std::result::Result<T, E>
 where
     F: FnOnce(&mut ClarityDatabase) -> std::result::Result<T, E>,
@@ -352,6 +956,160 @@ pub fn crosscheck_compare_only_advancing_tip(snippet: &str, count: u32) {
     );
 }
 
+/// Everything observable after deploying a snippet in a fresh environment.
+#[derive(Debug)]
+pub struct ExecutionOutcome {
+    /// The value returned by the snippet, or the error it failed with.
+    pub result: Result<Option<Value>, Error>,
+    /// The changes made to the datastore.
+    pub data: DatastoreDiff,
+    /// The events emitted.
+    pub events: Vec<StacksTransactionEvent>,
+}
+
+impl ExecutionOutcome {
+    fn new(snippet: &str, compiled: bool) -> Self {
+        let mut env = TestEnvironment::default();
+        env.checkpoint("genesis");
+        let result = if compiled {
+            env.evaluate(snippet)
+        } else {
+            env.interpret(snippet)
+        };
+        let data = env
+            .diff("genesis")
+            .expect("Failed to diff the environment.")
+            .data;
+
+        Self {
+            result,
+            data,
+            events: env.events.clone(),
+        }
+    }
+
+    /// The value, or the variant of the error, which must match between the
+    /// compiled and interpreted executions. The traps of the compiled
+    /// contract are mapped to the `RuntimeErrorType` raised by the
+    /// interpreter, see `map_runtime_error`, and the stack trace, which only
+    /// the interpreter reports, is ignored.
+    fn result_summary(&self) -> String {
+        match &self.result {
+            Ok(Some(value)) => value.to_string(),
+            Ok(None) => "none".to_string(),
+            Err(Error::Runtime(error, _)) => format!("runtime error: {:?}", error),
+            Err(Error::Unchecked(error)) => format!("check error: {:?}", error),
+            Err(Error::ShortReturn(short_return)) => format!("short return: {:?}", short_return),
+            Err(error) => format!("error: {:?}", error),
+        }
+    }
+}
+
+/// A difference between the compiled and interpreted executions of a
+/// snippet.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Divergence {
+    /// The returned values, or the error variants, differ.
+    Result {
+        compiled: String,
+        interpreted: String,
+    },
+    /// The changes made to the datastore differ.
+    Data {
+        compiled: DatastoreDiff,
+        interpreted: DatastoreDiff,
+    },
+    /// The emitted events differ.
+    Events {
+        compiled: Vec<String>,
+        interpreted: Vec<String>,
+    },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::Result {
+                compiled,
+                interpreted,
+            } => write!(
+                f,
+                "results diverge\n  compiled:    {compiled}\n  interpreted: {interpreted}"
+            ),
+            Divergence::Data {
+                compiled,
+                interpreted,
+            } => write!(
+                f,
+                "datastore changes diverge\n  compiled:    {compiled:?}\n  interpreted: {interpreted:?}"
+            ),
+            Divergence::Events {
+                compiled,
+                interpreted,
+            } => write!(
+                f,
+                "events diverge\n  compiled:    {compiled:?}\n  interpreted: {interpreted:?}"
+            ),
+        }
+    }
+}
+
+/// The result of running a snippet with both the compiler and the
+/// interpreter.
+#[derive(Debug)]
+pub struct CrosscheckReport {
+    pub compiled: ExecutionOutcome,
+    pub interpreted: ExecutionOutcome,
+    /// Every difference between the two executions. Empty if they agree.
+    pub divergences: Vec<Divergence>,
+}
+
+impl CrosscheckReport {
+    pub fn is_ok(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+/// Deploy `snippet` in fresh environments with both the compiler and the
+/// interpreter, and compare their results, datastore changes and events.
+/// Costs are not compared: the environments use a free cost tracker, as the
+/// cost contracts are not deployed in them.
+pub fn crosscheck_report(snippet: &str) -> CrosscheckReport {
+    let compiled = ExecutionOutcome::new(snippet, true);
+    let interpreted = ExecutionOutcome::new(snippet, false);
+
+    let mut divergences = Vec::new();
+    let (compiled_result, interpreted_result) =
+        (compiled.result_summary(), interpreted.result_summary());
+    if compiled_result != interpreted_result {
+        divergences.push(Divergence::Result {
+            compiled: compiled_result,
+            interpreted: interpreted_result,
+        });
+    }
+    if compiled.data != interpreted.data {
+        divergences.push(Divergence::Data {
+            compiled: compiled.data.clone(),
+            interpreted: interpreted.data.clone(),
+        });
+    }
+    if compiled.events != interpreted.events {
+        let describe = |events: &[StacksTransactionEvent]| {
+            events.iter().map(|event| format!("{:?}", event)).collect()
+        };
+        divergences.push(Divergence::Events {
+            compiled: describe(&compiled.events),
+            interpreted: describe(&interpreted.events),
+        });
+    }
+
+    CrosscheckReport {
+        compiled,
+        interpreted,
+        divergences,
+    }
+}
+
 pub fn crosscheck_validate<V: Fn(Value)>(snippet: &str, validator: V) {
     let compiled = evaluate_at(snippet, StacksEpochId::latest(), ClarityVersion::latest());
     let interpreted = interpret(snippet);
@@ -373,3 +1131,342 @@ pub fn crosscheck_validate<V: Fn(Value)>(snippet: &str, validator: V) {
 fn test_evaluate_snippet() {
     assert_eq!(evaluate("(+ 1 2)"), Ok(Some(Value::Int(3))));
 }
+
+#[test]
+fn test_call_functions() {
+    let mut env = TestEnvironment::default();
+    env.init_contract_with_snippet(
+        "counter",
+        "
+(define-data-var count uint u0)
+(define-private (add (n uint)) (begin (var-set count (+ (var-get count) n)) (var-get count)))
+(define-public (increment (n uint))
+  (let ((total (add n)))
+    (asserts! (< total u10) (err total))
+    (ok total)))
+(define-read-only (get-count) (var-get count))
+(define-read-only (get-sender) tx-sender)
+",
+    )
+    .unwrap();
+    let sender = PrincipalData::Standard(StandardPrincipalData::transient());
+
+    let result = env
+        .call_public(
+            "counter",
+            "increment",
+            &[Value::UInt(3)],
+            sender.clone(),
+            None,
+        )
+        .unwrap();
+    assert_eq!(result.value, Value::okay(Value::UInt(3)).unwrap());
+    assert!(result.committed);
+
+    // The failing call is rolled back.
+    let result = env
+        .call_public(
+            "counter",
+            "increment",
+            &[Value::UInt(10)],
+            sender.clone(),
+            None,
+        )
+        .unwrap();
+    assert_eq!(result.value, Value::error(Value::UInt(13)).unwrap());
+    assert!(!result.committed);
+
+    let result = env
+        .call_read_only("counter", "get-count", &[], sender.clone(), None)
+        .unwrap();
+    assert_eq!(result.value, Value::UInt(3));
+
+    let result = env
+        .call_private("counter", "add", &[Value::UInt(1)], sender.clone(), None)
+        .unwrap();
+    assert_eq!(result.value, Value::UInt(4));
+
+    let other = PrincipalData::parse("ST1PQHQKV0RJXZFY1DGX8MNSNYVE3VGZJSRTPGZGM").unwrap();
+    let result = env
+        .call_read_only("counter", "get-sender", &[], other.clone(), None)
+        .unwrap();
+    assert_eq!(result.value, Value::Principal(other));
+
+    // The function must have the expected type.
+    match env.call_public("counter", "get-count", &[], sender, None) {
+        Err(Error::Unchecked(CheckErrors::Expects(message))) => assert_eq!(
+            message,
+            "expected a public function, but counter.get-count is read-only"
+        ),
+        other => panic!("unexpected result: {other:?}"),
+    }
+}
+
+#[test]
+fn test_runtime_errors() {
+    let mut env = TestEnvironment::default();
+    assert!(matches!(
+        env.evaluate("(/ u1 u0)"),
+        Err(Error::Runtime(RuntimeErrorType::DivisionByZero, _))
+    ));
+
+    // Raised by the standard library, which does not know the location
+    assert_eq!(
+        env.last_runtime_error(),
+        Some(&RuntimeErrorReport {
+            code: crate::wasm_generator::ErrorCode::DivideByZero,
+            span: clarity::vm::representations::Span::zero(),
+        })
+    );
+
+    env.init_contract_with_snippet(
+        "panics",
+        "(define-read-only (get-value (value (optional uint)))
+  (+ u1
+     (unwrap-panic value)))",
+    )
+    .unwrap();
+    assert_eq!(env.last_runtime_error(), None);
+    let sender = PrincipalData::Standard(StandardPrincipalData::transient());
+    assert!(matches!(
+        env.call_read_only("panics", "get-value", &[Value::none()], sender, None),
+        Err(Error::Runtime(RuntimeErrorType::UnwrapFailure, _))
+    ));
+    assert_eq!(
+        env.last_runtime_error(),
+        Some(&RuntimeErrorReport {
+            code: crate::wasm_generator::ErrorCode::Panic,
+            span: clarity::vm::representations::Span {
+                start_line: 3,
+                start_column: 6,
+                end_line: 3,
+                end_column: 25,
+            },
+        })
+    );
+}
+
+#[test]
+fn test_checkpoints() {
+    let mut env = TestEnvironment::default();
+    env.init_contract_with_snippet(
+        "counter",
+        "
+(define-data-var count uint u0)
+(define-public (increment) (ok (var-set count (+ (var-get count) u1))))
+(define-read-only (get-count) (var-get count))
+",
+    )
+    .unwrap();
+    let sender = PrincipalData::Standard(StandardPrincipalData::transient());
+
+    env.checkpoint("deployed");
+    assert!(env.diff("deployed").unwrap().data.is_empty());
+
+    env.call_public("counter", "increment", &[], sender.clone(), None)
+        .unwrap();
+    env.call_public("counter", "increment", &[], sender.clone(), None)
+        .unwrap();
+    env.advance_chain_tip(1);
+
+    let diff = env.diff("deployed").unwrap();
+    assert_eq!(diff.data.modified.len(), 1);
+    assert!(diff.data.added.is_empty());
+    assert!(diff.new_contracts.is_empty());
+    assert_eq!(diff.chain_height, (0, 1));
+
+    env.restore("deployed").unwrap();
+    let result = env
+        .call_read_only("counter", "get-count", &[], sender.clone(), None)
+        .unwrap();
+    assert_eq!(result.value, Value::UInt(0));
+    assert!(env.diff("deployed").unwrap().data.is_empty());
+
+    assert!(env.restore("unknown").is_err());
+}
+
+#[test]
+fn test_crosscheck_report() {
+    let report = crosscheck_report(
+        "
+(define-data-var count uint u1)
+(var-set count (+ (var-get count) u1))
+(print (var-get count))
+",
+    );
+    assert!(report.is_ok(), "{:?}", report.divergences);
+    assert_eq!(report.compiled.events.len(), 1);
+    assert!(!report.compiled.data.is_empty());
+
+    let report = crosscheck_report("(/ 1 0)");
+    assert!(report.is_ok(), "{:?}", report.divergences);
+    assert!(matches!(
+        report.compiled.result,
+        Err(Error::Runtime(RuntimeErrorType::DivisionByZero, _))
+    ));
+}
+
+#[test]
+fn test_block_info_overrides() {
+    use clarity::types::chainstate::{ConsensusHash, StacksAddress, VRFSeed};
+
+    let mut env = TestEnvironment::default();
+    let miner = StacksAddress::burn_address(false);
+    env.set_block_settings(BlockSettings {
+        time_spacing: 600,
+        miner,
+        ..BlockSettings::default()
+    });
+    env.advance_chain_tip(2);
+
+    let mut block_info = env.get_block_info(1).unwrap().clone();
+    block_info.vrf_seed = VRFSeed([7; 32]);
+    env.set_block_info(1, block_info);
+
+    // Block 3 is derived from block 2, with its own consensus hash
+    let mut block_info = env.get_block_info(2).unwrap().clone();
+    block_info.consensus_hash = ConsensusHash([3; 20]);
+    block_info.burn_block_time += 5;
+    env.set_block_info(3, block_info);
+    env.advance_chain_tip(1);
+
+    assert_eq!(
+        env.evaluate(
+            "(- (unwrap-panic (get-block-info? time u2)) (unwrap-panic (get-block-info? time u1)))"
+        )
+        .unwrap(),
+        Some(Value::UInt(600))
+    );
+    assert_eq!(
+        env.evaluate("(get-block-info? miner-address u2)").unwrap(),
+        Some(Value::some(Value::Principal(PrincipalData::from(miner))).unwrap())
+    );
+    assert_eq!(
+        env.evaluate("(get-block-info? vrf-seed u1)").unwrap(),
+        Some(Value::some(Value::buff_from(vec![7; 32]).unwrap()).unwrap())
+    );
+    assert_eq!(
+        env.get_block_info(3).unwrap().burn_block_time,
+        env.get_block_info(2).unwrap().burn_block_time + 5
+    );
+    assert_eq!(env.get_block_info(3).unwrap().burn_block_height, 3);
+}
+
+#[test]
+fn test_genesis_wallets() {
+    let mut env = TestEnvironment::new_with_genesis(
+        StacksEpochId::latest(),
+        ClarityVersion::latest(),
+        &Genesis::with_wallets(2, 1_000),
+    );
+    let (wallet_1, wallet_2) = (test_wallet("wallet_1"), test_wallet("wallet_2"));
+    assert_eq!(test_wallet("wallet_1"), wallet_1);
+    assert_ne!(wallet_1, wallet_2);
+    assert_eq!(env.get_stx_balance(&wallet_1), 1_000);
+
+    env.init_contract_with_snippet(
+        "tokens",
+        &format!(
+            "
+(define-fungible-token gold)
+(define-non-fungible-token badge uint)
+(ft-mint? gold u50 '{wallet_1})
+(nft-mint? badge u1 '{wallet_2})
+(define-public (pay (amount uint) (recipient principal))
+  (stx-transfer? amount tx-sender recipient))
+"
+        ),
+    )
+    .unwrap();
+
+    let result = env
+        .call_public(
+            "tokens",
+            "pay",
+            &[Value::UInt(400), Value::Principal(wallet_2.clone())],
+            wallet_1.clone(),
+            None,
+        )
+        .unwrap();
+    assert!(result.committed);
+    assert_eq!(env.get_stx_balance(&wallet_1), 600);
+    assert_eq!(env.get_stx_balance(&wallet_2), 1_400);
+
+    env.set_stx_balance(&wallet_1, 10);
+    assert_eq!(env.get_stx_balance(&wallet_1), 10);
+    env.mint_stx(&wallet_1, 5);
+    assert_eq!(env.get_stx_balance(&wallet_1), 15);
+
+    assert_eq!(env.get_ft_balance("tokens", "gold", &wallet_1).unwrap(), 50);
+    assert_eq!(env.get_ft_balance("tokens", "gold", &wallet_2).unwrap(), 0);
+    assert_eq!(
+        env.get_nft_owner("tokens", "badge", &Value::UInt(1))
+            .unwrap(),
+        Some(wallet_2)
+    );
+    assert_eq!(
+        env.get_nft_owner("tokens", "badge", &Value::UInt(2))
+            .unwrap(),
+        None
+    );
+}
+
+#[test]
+fn test_coverage_lcov() {
+    let mut env = TestEnvironment::default();
+    env.enable_coverage();
+    env.init_contract_with_snippet(
+        "checker",
+        "(define-public (check (n uint))
+  (if (> n u10)
+    (ok true)
+    (err false)))
+(define-public (unused)
+  (ok u1))",
+    )
+    .unwrap();
+
+    let sender = PrincipalData::Standard(StandardPrincipalData::transient());
+    env.call_public("checker", "check", &[Value::UInt(20)], sender.clone(), None)
+        .unwrap();
+    env.call_public("checker", "check", &[Value::UInt(1)], sender, None)
+        .unwrap();
+
+    // The coverage reports are not visible as events.
+    assert!(env.events().is_empty());
+
+    let lcov = env.coverage_lcov(|name| format!("contracts/{name}.clar"));
+    assert!(lcov.starts_with("TN:\nSF:contracts/checker.clar\n"));
+    for record in [
+        "BRDA:2,0,0,1\n",
+        "BRDA:2,0,1,1\n",
+        "BRF:2\nBRH:2\n",
+        "DA:1,1\n",
+        "DA:2,2\n",
+        "DA:3,1\n",
+        "DA:4,1\n",
+        "DA:5,1\n",
+        "DA:6,0\n",
+        "LF:6\nLH:5\n",
+    ] {
+        assert!(lcov.contains(record), "missing {record:?} in\n{lcov}");
+    }
+}
+
+#[test]
+fn test_restore_coverage() {
+    let mut env = TestEnvironment::default();
+    env.enable_coverage();
+    env.init_contract_with_snippet("counter", "(define-public (bump) (ok u1))")
+        .unwrap();
+    env.checkpoint("deployed");
+    let lcov = env.coverage_lcov(|name| format!("{name}.clar"));
+
+    let sender = PrincipalData::Standard(StandardPrincipalData::transient());
+    env.call_public("counter", "bump", &[], sender, None)
+        .unwrap();
+    assert_ne!(env.coverage_lcov(|name| format!("{name}.clar")), lcov);
+
+    env.restore("deployed").unwrap();
+    assert_eq!(env.coverage_lcov(|name| format!("{name}.clar")), lcov);
+}
@@ -4,6 +4,8 @@ use std::collections::HashMap;
 use clarity::vm::analysis::ContractAnalysis;
 use clarity::vm::clarity_wasm::{get_type_in_memory_size, get_type_size, is_in_memory_type};
 use clarity::vm::diagnostic::DiagnosableError;
+use clarity::vm::errors::RuntimeErrorType;
+use clarity::vm::representations::Span;
 use clarity::vm::types::signatures::{StringUTF8Length, BUFF_1};
 use clarity::vm::types::{
     CharType, FixedFunction, FunctionType, PrincipalData, SequenceData, SequenceSubtype,
@@ -11,6 +13,7 @@ use clarity::vm::types::{
 };
 use clarity::vm::variables::NativeVariables;
 use clarity::vm::{ClarityName, SymbolicExpression, SymbolicExpressionType};
+use serde::{Deserialize, Serialize};
 use walrus::ir::{
     BinaryOp, IfElse, InstrSeqId, InstrSeqType, LoadKind, MemArg, StoreKind, UnaryOp,
 };
@@ -19,10 +22,13 @@ use walrus::{
     MemoryId, Module, ValType,
 };
 
-use crate::words;
+use crate::abi::ContractAbi;
+use crate::coverage::{CoverageMap, Instrumentation};
+use crate::source_map::SourceMapSection;
+use crate::{constant_folding, words, TargetNetwork};
 
 // First free position after data directly defined in standard.wat
-pub const END_OF_STANDARD_DATA: u32 = 1352;
+pub const END_OF_STANDARD_DATA: u32 = 1400;
 
 /// WasmGenerator is a Clarity AST visitor that generates a WebAssembly module
 /// as it traverses the AST.
@@ -36,8 +42,10 @@ pub struct WasmGenerator {
     pub(crate) literal_memory_end: u32,
     /// Global ID of the stack pointer.
     pub(crate) stack_pointer: GlobalId,
-    /// Map strings saved in the literal memory to their offset.
+    /// Map literals saved in the literal memory to their offset.
     pub(crate) literal_memory_offset: HashMap<LiteralMemoryEntry, u32>,
+    /// Statistics of the generation.
+    stats: CompileStats,
     /// Map constants to an offset in the literal memory.
     pub(crate) constants: HashMap<String, u32>,
     /// The current function body block, used for early exit
@@ -49,14 +57,53 @@ pub struct WasmGenerator {
 
     /// The locals for the current function.
     pub(crate) bindings: HashMap<String, Vec<LocalId>>,
+    /// Local holding the frame pointer of the current function.
+    frame_pointer: LocalId,
     /// Size of the current function's stack frame.
     frame_size: i32,
+    /// Sum of the stack frames of all functions, used for the initial size of
+    /// the memory.
+    stack_size: i32,
+    /// Maximum number of pages the memory can grow to, if limited.
+    max_memory_pages: Option<u32>,
+    /// Span of the innermost expression which failed to be generated.
+    error_span: Option<Span>,
+    /// The coverage instrumentation, if enabled.
+    coverage: Option<Instrumentation>,
+    /// The source map of the generated functions, if enabled.
+    source_map: Option<SourceMapSection>,
+    /// The network targeted by the contract, if known at compile time.
+    network: Option<TargetNetwork>,
+    /// The values of the expressions folded at compile time, by expression
+    /// ID.
+    folded: HashMap<u64, clarity::vm::Value>,
 }
 
 #[derive(Hash, Eq, PartialEq)]
 pub enum LiteralMemoryEntry {
     Ascii(String),
     Utf8(String),
+    /// Any other literal value, keyed by its consensus serialization.
+    Value(Vec<u8>),
+}
+
+/// Statistics of the generation of a module.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompileStats {
+    /// Bytes of the literals written in the literal memory.
+    pub literal_bytes: u32,
+    /// Bytes saved by reusing the literals already written in the literal
+    /// memory.
+    pub literal_bytes_saved: u32,
+}
+
+/// The result of the generation of a module.
+#[derive(Debug)]
+pub struct GeneratorOutput {
+    pub module: Module,
+    /// The coverage map, if the module is instrumented for coverage.
+    pub coverage: Option<CoverageMap>,
+    pub stats: CompileStats,
 }
 
 #[derive(Debug)]
@@ -66,6 +113,8 @@ pub enum GeneratorError {
     TypeError(String),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum FunctionKind {
     Public,
     Private,
@@ -86,6 +135,112 @@ impl DiagnosableError for GeneratorError {
     }
 }
 
+/// `ErrorCode` matches the codes passed to `stdlib.runtime-error` in the
+/// standard library. When a runtime error occurs, the code is stored in the
+/// exported `runtime-error-code` global before trapping, and the span of the
+/// Clarity expression which raised it, when known, in the
+/// `runtime-error-{start,end}-{line,column}` globals. Both are also printed
+/// to the host, see `RuntimeErrorReport`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum ErrorCode {
+    Overflow = 0,
+    Underflow = 1,
+    DivideByZero = 2,
+    LogOfNumberLessThanOrEqualToZero = 3,
+    ExpectedANonNegativeNumber = 4,
+    BufferToIntegerLength = 5,
+    Panic = 6,
+    ShortReturn = 7,
+    MemoryLimitExceeded = 8,
+}
+
+impl TryFrom<i32> for ErrorCode {
+    type Error = i32;
+
+    fn try_from(code: i32) -> Result<Self, Self::Error> {
+        Ok(match code {
+            0 => ErrorCode::Overflow,
+            1 => ErrorCode::Underflow,
+            2 => ErrorCode::DivideByZero,
+            3 => ErrorCode::LogOfNumberLessThanOrEqualToZero,
+            4 => ErrorCode::ExpectedANonNegativeNumber,
+            5 => ErrorCode::BufferToIntegerLength,
+            6 => ErrorCode::Panic,
+            7 => ErrorCode::ShortReturn,
+            8 => ErrorCode::MemoryLimitExceeded,
+            _ => return Err(code),
+        })
+    }
+}
+
+impl ErrorCode {
+    /// Returns the `RuntimeErrorType` that the interpreter raises for the
+    /// same error, or `None` for a short return, which the interpreter
+    /// reports as `Error::ShortReturn` instead, and for exceeding the memory
+    /// limit, which has no equivalent in the interpreter.
+    pub fn runtime_error(self) -> Option<RuntimeErrorType> {
+        match self {
+            ErrorCode::Overflow => Some(RuntimeErrorType::ArithmeticOverflow),
+            ErrorCode::Underflow => Some(RuntimeErrorType::ArithmeticUnderflow),
+            ErrorCode::DivideByZero => Some(RuntimeErrorType::DivisionByZero),
+            ErrorCode::LogOfNumberLessThanOrEqualToZero => Some(RuntimeErrorType::Arithmetic(
+                "log2 must be passed a positive integer".to_owned(),
+            )),
+            ErrorCode::ExpectedANonNegativeNumber => Some(RuntimeErrorType::Arithmetic(
+                "sqrti must be passed a positive integer".to_owned(),
+            )),
+            ErrorCode::BufferToIntegerLength => Some(RuntimeErrorType::Arithmetic(
+                "buffer to integer expects a buffer length <= 16".to_owned(),
+            )),
+            ErrorCode::Panic => Some(RuntimeErrorType::UnwrapFailure),
+            ErrorCode::ShortReturn | ErrorCode::MemoryLimitExceeded => None,
+        }
+    }
+}
+
+/// Prefix of the buffer printed by `stdlib.runtime-error` to report a runtime
+/// error to the host.
+pub const RUNTIME_ERROR_MAGIC: &[u8] = b"clar2wasm-runtime-error";
+
+/// A runtime error, as printed by `stdlib.runtime-error` before trapping.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeErrorReport {
+    pub code: ErrorCode,
+    /// The span of the Clarity expression which raised the error, or all 0
+    /// if it is unknown, like for the errors raised by the standard library
+    /// itself.
+    pub span: Span,
+}
+
+impl RuntimeErrorReport {
+    /// The report printed in `value`, or `None` if `value` is not a runtime
+    /// error report.
+    pub fn parse(value: &clarity::vm::Value) -> Option<Self> {
+        let clarity::vm::Value::Sequence(SequenceData::Buffer(buffer)) = value else {
+            return None;
+        };
+        let fields: Vec<_> = buffer
+            .data
+            .strip_prefix(RUNTIME_ERROR_MAGIC)?
+            .chunks_exact(4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect();
+        match fields[..] {
+            [code, start_line, start_column, end_line, end_column] => Some(RuntimeErrorReport {
+                code: ErrorCode::try_from(code as i32).ok()?,
+                span: Span {
+                    start_line,
+                    start_column,
+                    end_line,
+                    end_column,
+                },
+            }),
+            _ => None,
+        }
+    }
+}
+
 pub trait ArgumentsExt {
     fn get_expr(&self, n: usize) -> Result<&SymbolicExpression, GeneratorError>;
     fn get_name(&self, n: usize) -> Result<&ClarityName, GeneratorError>;
@@ -211,7 +366,7 @@ impl WasmGenerator {
     pub fn new(contract_analysis: ContractAnalysis) -> Result<WasmGenerator, GeneratorError> {
         let standard_lib_wasm: &[u8] = include_bytes!("standard/standard.wasm");
 
-        let module = Module::from_buffer(standard_lib_wasm).map_err(|_err| {
+        let mut module = Module::from_buffer(standard_lib_wasm).map_err(|_err| {
             GeneratorError::InternalError("failed to load standard library".to_owned())
         })?;
         // Get the stack-pointer global ID
@@ -232,21 +387,95 @@ impl WasmGenerator {
                 )
             })?;
 
+        let frame_pointer = module.locals.add(ValType::I32);
+        let folded = constant_folding::fold_all(
+            &contract_analysis.expressions,
+            contract_analysis.clarity_version,
+            contract_analysis.epoch,
+        );
+
         Ok(WasmGenerator {
             contract_analysis,
             module,
             literal_memory_end: END_OF_STANDARD_DATA,
             stack_pointer: global_id,
             literal_memory_offset: HashMap::new(),
+            stats: CompileStats::default(),
             constants: HashMap::new(),
             bindings: HashMap::new(),
             early_return_block_id: None,
             return_type: None,
+            frame_pointer,
             frame_size: 0,
+            stack_size: 0,
+            max_memory_pages: None,
             datavars_types: HashMap::new(),
+            error_span: None,
+            coverage: None,
+            source_map: None,
+            network: None,
+            folded,
         })
     }
 
+    /// Instrument the generated module to count the executions of each
+    /// expression. See the `coverage` module.
+    pub fn with_coverage(mut self) -> Self {
+        self.coverage = Some(Instrumentation::new(&mut self.module));
+        self
+    }
+
+    /// Add a source map of the generated functions to the module. See the
+    /// `source_map` module.
+    pub fn with_source_map(mut self) -> Self {
+        self.source_map = Some(SourceMapSection::default());
+        self
+    }
+
+    /// Compile the constants of `network` into the module. The host imports
+    /// `is_in_mainnet`, `is_in_regtest` and `chain_id` are replaced by
+    /// functions returning these constants, for the standard library, and
+    /// the generated code uses the constants directly.
+    pub fn with_network(mut self, network: TargetNetwork) -> Result<Self, GeneratorError> {
+        let mut replace_import = |name: &str, body: &dyn Fn(&mut InstrSeqBuilder)| {
+            let function = self.func_by_name(name);
+            self.module
+                .replace_imported_func(function, |(builder, _args)| body(builder))
+                .map_err(|e| {
+                    GeneratorError::InternalError(format!("failed to replace {name}: {e}"))
+                })
+        };
+        replace_import("stdlib.is_in_mainnet", &|builder| {
+            builder.i32_const(network.is_in_mainnet() as i32);
+        })?;
+        replace_import("stdlib.is_in_regtest", &|builder| {
+            builder.i32_const(network.is_in_regtest() as i32);
+        })?;
+        replace_import("stdlib.chain_id", &|builder| {
+            builder.i64_const(network.chain_id as i64).i64_const(0);
+        })?;
+
+        self.network = Some(network);
+        Ok(self)
+    }
+
+    /// Limit the memory to `pages` pages of 64 KiB. The memory grows as the
+    /// call stack needs it, up to this limit, after which the contract
+    /// raises a `MemoryLimitExceeded` runtime error.
+    pub fn with_max_memory_pages(mut self, pages: u32) -> Self {
+        self.max_memory_pages = Some(pages);
+        self
+    }
+
+    /// Emits whether the contract runs in mainnet, from the target network if
+    /// known, or from the host.
+    pub(crate) fn is_in_mainnet(&self, builder: &mut InstrSeqBuilder) {
+        match self.network {
+            Some(network) => builder.i32_const(network.is_in_mainnet() as i32),
+            None => builder.call(self.func_by_name("stdlib.is_in_mainnet")),
+        };
+    }
+
     pub fn set_memory_pages(&mut self) -> Result<(), GeneratorError> {
         let memory = self
             .module
@@ -255,16 +484,54 @@ impl WasmGenerator {
             .next()
             .ok_or_else(|| GeneratorError::InternalError("No Memory found".to_owned()))?;
 
-        let total_memory_bytes = self.literal_memory_end + (self.frame_size as u32);
-        let pages_required = total_memory_bytes / (64 * 1024);
-        let remainder = total_memory_bytes % (64 * 1024);
-
-        memory.initial = pages_required + (remainder > 0) as u32;
+        let pages = |bytes: u32| bytes / (64 * 1024) + (bytes % (64 * 1024) > 0) as u32;
+        let pages_required = pages(self.literal_memory_end + (self.stack_size as u32));
+
+        // The memory grows as the call stack needs it, so only the literal
+        // memory has to fit in the limit.
+        match self.max_memory_pages {
+            Some(max) if pages(self.literal_memory_end) > max => {
+                return Err(GeneratorError::InternalError(format!(
+                    "the literal memory needs {} pages, more than the maximum of {max}",
+                    pages(self.literal_memory_end)
+                )));
+            }
+            Some(max) => {
+                memory.initial = pages_required.min(max);
+                memory.maximum = Some(max);
+            }
+            None => memory.initial = pages_required,
+        }
 
         Ok(())
     }
 
-    pub fn generate(mut self) -> Result<Module, GeneratorError> {
+    pub fn generate(self) -> Result<Module, GeneratorError> {
+        self.generate_with_span().map_err(|(error, _span)| error)
+    }
+
+    /// Generate the module like `generate`, but on failure, also return the
+    /// span of the innermost expression which could not be generated, if
+    /// any.
+    pub fn generate_with_span(self) -> Result<Module, (GeneratorError, Option<Span>)> {
+        self.generate_output().map(|output| output.module)
+    }
+
+    /// Generate the module like `generate_with_span`, and also return the
+    /// coverage map if the module is instrumented, and the statistics of the
+    /// generation.
+    pub fn generate_output(mut self) -> Result<GeneratorOutput, (GeneratorError, Option<Span>)> {
+        match self.traverse_contract() {
+            Ok(coverage) => Ok(GeneratorOutput {
+                module: self.module,
+                coverage,
+                stats: self.stats,
+            }),
+            Err(error) => Err((error, self.error_span)),
+        }
+    }
+
+    fn traverse_contract(&mut self) -> Result<Option<CoverageMap>, GeneratorError> {
         let expressions = std::mem::take(&mut self.contract_analysis.expressions);
 
         // Get the type of the last top-level expression with a return value
@@ -276,16 +543,52 @@ impl WasmGenerator {
             .map_or_else(Vec::new, clar2wasm_ty);
 
         let mut current_function = FunctionBuilder::new(&mut self.module.types, &[], &return_ty);
+        current_function.name(".top-level".to_owned());
+        self.name_locals("frame-pointer", &[self.frame_pointer]);
+
+        let mut func_body = current_function.func_body();
+        let mut block = func_body.dangling_instr_seq(InstrSeqType::new(
+            &mut self.module.types,
+            &[],
+            &return_ty,
+        ));
+        let block_id = block.id();
 
         if !expressions.is_empty() {
-            self.traverse_statement_list(&mut current_function.func_body(), &expressions)?;
+            self.traverse_statement_list(&mut block, &expressions)?;
         }
 
         self.contract_analysis.expressions = expressions;
 
+        // The frame size is only known once the body has been traversed, so
+        // the prelude is added before the body block afterwards.
+        self.frame_prelude(&mut func_body);
+        self.coverage_enter(&mut func_body);
+        func_body.instr(walrus::ir::Block { seq: block_id });
+        self.coverage_exit(&mut func_body);
+
         let top_level = current_function.finish(vec![], &mut self.module.funcs);
         self.module.exports.add(".top-level", top_level);
 
+        ContractAbi::new(&self.contract_analysis)?.add_to_module(&mut self.module)?;
+
+        if let Some(mut source_map) = self.source_map.take() {
+            source_map.add_function(top_level);
+            self.module.customs.add(source_map);
+        }
+
+        // The counters region is the last part of the literal memory.
+        let coverage = match self.coverage.take() {
+            Some(instrumentation) => {
+                let memory = self.get_memory()?;
+                let (map, size) =
+                    instrumentation.finish(&mut self.module, memory, self.literal_memory_end);
+                self.literal_memory_end += size;
+                Some(map)
+            }
+            None => None,
+        };
+
         self.set_memory_pages()?;
 
         // Update the initial value of the stack-pointer to point beyond the
@@ -294,7 +597,7 @@ impl WasmGenerator {
             walrus::InitExpr::Value(walrus::ir::Value::I32(self.literal_memory_end as i32)),
         );
 
-        Ok(self.module)
+        Ok(coverage)
     }
 
     pub fn get_memory(&self) -> Result<MemoryId, GeneratorError> {
@@ -312,14 +615,42 @@ impl WasmGenerator {
         builder: &mut InstrSeqBuilder,
         expr: &SymbolicExpression,
     ) -> Result<(), GeneratorError> {
-        match &expr.expr {
+        let start = builder.instrs().len();
+
+        if self.coverage.is_some() {
+            let memory = self.get_memory()?;
+            if let Some(coverage) = self.coverage.as_mut() {
+                coverage.count(builder, memory, expr);
+            }
+        }
+
+        let result = match &expr.expr {
             SymbolicExpressionType::Atom(name) => self.visit_atom(builder, expr, name),
-            SymbolicExpressionType::List(exprs) => self.traverse_list(builder, expr, exprs),
+            SymbolicExpressionType::List(exprs) => match self.folded.get(&expr.id).cloned() {
+                Some(value) => self.visit_constant_value(builder, expr, &value),
+                None => self.traverse_list(builder, expr, exprs),
+            },
             SymbolicExpressionType::LiteralValue(value) => {
                 self.visit_literal_value(builder, expr, value)
             }
             _ => Ok(()),
+        };
+        if result.is_err() && self.error_span.is_none() {
+            self.error_span = Some(expr.span().clone());
+        }
+
+        // Tag the instructions emitted for this expression, which were not
+        // already tagged by a sub-expression.
+        if let Some(source_map) = self.source_map.as_mut() {
+            let instrs = &mut builder.instrs_mut()[start..];
+            if instrs.iter().any(|(_, loc)| loc.is_default()) {
+                let location = source_map.location(expr.span());
+                for (_, loc) in instrs.iter_mut().filter(|(_, loc)| loc.is_default()) {
+                    *loc = location;
+                }
+            }
         }
+        result
     }
 
     fn traverse_list(
@@ -462,6 +793,7 @@ impl WasmGenerator {
                 plocals.push(local);
                 params_types.push(ty);
             }
+            self.name_locals(param.name.as_str(), &plocals);
             bindings.insert(param.name.to_string(), plocals.clone());
         }
 
@@ -474,12 +806,12 @@ impl WasmGenerator {
         func_builder.name(name.as_str().to_string());
         let mut func_body = func_builder.func_body();
 
-        // Function prelude
-        // Save the frame pointer in a local variable.
+        // Setup the frame pointer of this function, saving the top-level frame
+        // to restore after.
         let frame_pointer = self.module.locals.add(ValType::I32);
-        func_body
-            .global_get(self.stack_pointer)
-            .local_set(frame_pointer);
+        self.name_locals("frame-pointer", &[frame_pointer]);
+        let top_level_frame_pointer = std::mem::replace(&mut self.frame_pointer, frame_pointer);
+        let top_level_frame_size = std::mem::replace(&mut self.frame_size, 0);
 
         // Setup the locals map for this function, saving the top-level map to
         // restore after.
@@ -498,8 +830,15 @@ impl WasmGenerator {
         self.set_expr_type(body, function_type.returns.clone())?;
         self.traverse_expr(&mut block, body)?;
 
+        // Function prelude
+        // The frame size is only known once the body has been traversed, so
+        // the prelude is added before the body block afterwards.
+        self.frame_prelude(&mut func_body);
+        self.coverage_enter(&mut func_body);
+
         // Insert the function body block into the function
         func_body.instr(walrus::ir::Block { seq: block_id });
+        self.coverage_exit(&mut func_body);
 
         // Function postlude
         // Restore the initial stack pointer.
@@ -507,30 +846,129 @@ impl WasmGenerator {
             .local_get(frame_pointer)
             .global_set(self.stack_pointer);
 
-        // Restore the top-level locals map.
+        // Restore the top-level locals map and frame.
         self.bindings = top_level_locals;
+        self.frame_pointer = top_level_frame_pointer;
+        self.frame_size = top_level_frame_size;
 
         // Reset the return type and early block to None
         self.return_type = None;
         self.early_return_block_id = None;
 
-        Ok(func_builder.finish(param_locals, &mut self.module.funcs))
+        let function = func_builder.finish(param_locals, &mut self.module.funcs);
+        if let Some(source_map) = self.source_map.as_mut() {
+            source_map.add_function(function);
+        }
+        Ok(function)
+    }
+
+    /// Emits the prelude of the current function, which saves the stack
+    /// pointer in the frame pointer, then reserves the whole frame with a
+    /// single update of the stack pointer, growing the memory if needed.
+    fn frame_prelude(&mut self, builder: &mut InstrSeqBuilder) {
+        builder.global_get(self.stack_pointer);
+        if self.frame_size > 0 {
+            // (global.set $stack-pointer (i32.add (local.tee $frame-pointer (global.get $stack-pointer)) (i32.const <frame_size>))
+            // (call $stdlib.ensure-memory (global.get $stack-pointer))
+            builder
+                .local_tee(self.frame_pointer)
+                .i32_const(self.frame_size)
+                .binop(BinaryOp::I32Add);
+            self.set_stack_pointer(builder);
+        } else {
+            builder.local_set(self.frame_pointer);
+        }
+        self.stack_size += self.frame_size;
     }
 
-    pub fn return_early(&self, builder: &mut InstrSeqBuilder) -> Result<(), GeneratorError> {
+    /// Emits a call to `stdlib.ensure-memory`, which grows the memory to
+    /// cover the address on top of the data stack, or raises a
+    /// `MemoryLimitExceeded` error if it cannot.
+    pub(crate) fn ensure_memory(&self, builder: &mut InstrSeqBuilder) {
+        builder.call(self.func_by_name("stdlib.ensure-memory"));
+    }
+
+    /// Pops the new top of the call stack from the data stack into
+    /// `$stack-pointer`, then grows the memory to cover it. Every bump of the
+    /// stack pointer must go through this, so that large values grow the
+    /// memory instead of writing out of bounds.
+    pub(crate) fn set_stack_pointer(&self, builder: &mut InstrSeqBuilder) {
+        builder
+            .global_set(self.stack_pointer)
+            .global_get(self.stack_pointer);
+        self.ensure_memory(builder);
+    }
+
+    /// Emits the entry in a contract function for the coverage
+    /// instrumentation, if enabled.
+    fn coverage_enter(&self, builder: &mut InstrSeqBuilder) {
+        if let Some(coverage) = &self.coverage {
+            coverage.enter(builder);
+        }
+    }
+
+    /// Emits the exit of a contract function for the coverage
+    /// instrumentation, if enabled. Since early returns branch to the end of
+    /// the body block, this is reached by all the returns, but not by runtime
+    /// errors, which abort the transaction anyway.
+    fn coverage_exit(&self, builder: &mut InstrSeqBuilder) {
+        if let Some(coverage) = &self.coverage {
+            coverage.exit(builder, self.func_by_name("stdlib.print"));
+        }
+    }
+
+    pub fn return_early(
+        &self,
+        builder: &mut InstrSeqBuilder,
+        expr: &SymbolicExpression,
+    ) -> Result<(), GeneratorError> {
         if let Some(block_id) = self.early_return_block_id {
             builder.instr(walrus::ir::Br { block: block_id });
         } else {
-            // This must be from a top-leve statement, so it should cause a runtime error
-            builder
-                .i32_const(7)
-                .call(self.func_by_name("stdlib.runtime-error"));
+            // This must be from a top-level statement, so it should cause a runtime error
+            self.runtime_error(builder, ErrorCode::ShortReturn, expr)?;
             builder.unreachable();
         }
 
         Ok(())
     }
 
+    /// Emits a call to `stdlib.runtime-error` with `code`, after recording the
+    /// span of `expr` in the runtime error location globals.
+    pub(crate) fn runtime_error(
+        &self,
+        builder: &mut InstrSeqBuilder,
+        code: ErrorCode,
+        expr: &SymbolicExpression,
+    ) -> Result<(), GeneratorError> {
+        let span = expr.span();
+        for (name, value) in [
+            ("runtime-error-start-line", span.start_line),
+            ("runtime-error-start-column", span.start_column),
+            ("runtime-error-end-line", span.end_line),
+            ("runtime-error-end-column", span.end_column),
+        ] {
+            builder
+                .i32_const(value as i32)
+                .global_set(self.global_by_name(name)?);
+        }
+        builder
+            .i32_const(code as i32)
+            .call(self.func_by_name("stdlib.runtime-error"));
+        Ok(())
+    }
+
+    fn global_by_name(&self, name: &str) -> Result<GlobalId, GeneratorError> {
+        self.module
+            .globals
+            .iter()
+            .find(|global| global.name.as_deref() == Some(name))
+            .map(|global| global.id())
+            .ok_or_else(|| {
+                GeneratorError::InternalError(format!("Expected to find a global named ${name}"))
+            })
+    }
+
     /// Gets the result type of the given `SymbolicExpression`.
     pub fn get_expr_type(&self, expr: &SymbolicExpression) -> Option<&TypeSignature> {
         self.contract_analysis
@@ -566,73 +1004,59 @@ impl WasmGenerator {
         &mut self,
         s: &CharType,
     ) -> Result<(u32, u32), GeneratorError> {
-        // If this string has already been saved in the literal memory,
-        // just return the offset and length.
         let (data, entry) = match s {
-            CharType::ASCII(s) => {
-                let entry = LiteralMemoryEntry::Ascii(s.to_string());
-                if let Some(offset) = self.literal_memory_offset.get(&entry) {
-                    return Ok((*offset, s.data.len() as u32));
-                }
-                (s.data.clone(), entry)
-            }
+            CharType::ASCII(s) => (s.data.clone(), LiteralMemoryEntry::Ascii(s.to_string())),
             CharType::UTF8(u) => {
                 let data_str = String::from_utf8(u.data.iter().flatten().cloned().collect())
                     .map_err(|_e| {
                         GeneratorError::InternalError("Invalid UTF-8 sequence".to_owned())
                     })?;
-                let entry = LiteralMemoryEntry::Utf8(data_str.clone());
-                if let Some(offset) = self.literal_memory_offset.get(&entry) {
-                    return Ok((*offset, u.data.len() as u32 * 4));
-                }
                 // Convert the string into 4-byte big-endian unicode scalar values.
                 let data = data_str
                     .chars()
                     .flat_map(|c| (c as u32).to_be_bytes())
                     .collect();
-                (data, entry)
+                (data, LiteralMemoryEntry::Utf8(data_str))
             }
         };
-        let memory = self.get_memory()?;
-        let offset = self.literal_memory_end;
-        let len = data.len() as u32;
-        self.module.data.add(
-            DataKind::Active(ActiveData {
-                memory,
-                location: walrus::ActiveDataLocation::Absolute(offset),
-            }),
-            data,
-        );
-        self.literal_memory_end += len;
-
-        // Save the offset in the literal memory for this string
-        self.literal_memory_offset.insert(entry, offset);
-
-        Ok((offset, len))
+        self.intern_literal(entry, data)
     }
 
     /// Adds a new string literal into the memory for an identifier
     pub(crate) fn add_string_literal(&mut self, name: &str) -> Result<(u32, u32), GeneratorError> {
-        // If this identifier has already been saved in the literal memory,
-        // just return the offset and length.
-        let entry = LiteralMemoryEntry::Ascii(name.to_string());
+        self.intern_literal(
+            LiteralMemoryEntry::Ascii(name.to_string()),
+            name.as_bytes().to_vec(),
+        )
+    }
+
+    /// Returns the offset and length of `data` in the literal memory. If a
+    /// literal was already saved for `entry`, it is reused, otherwise `data`
+    /// is saved at the end of the literal memory.
+    fn intern_literal(
+        &mut self,
+        entry: LiteralMemoryEntry,
+        data: Vec<u8>,
+    ) -> Result<(u32, u32), GeneratorError> {
+        let len = data.len() as u32;
         if let Some(offset) = self.literal_memory_offset.get(&entry) {
-            return Ok((*offset, name.len() as u32));
+            self.stats.literal_bytes_saved += len;
+            return Ok((*offset, len));
         }
 
         let memory = self.get_memory()?;
         let offset = self.literal_memory_end;
-        let len = name.len() as u32;
         self.module.data.add(
             DataKind::Active(ActiveData {
                 memory,
                 location: walrus::ActiveDataLocation::Absolute(offset),
             }),
-            name.as_bytes().to_vec(),
+            data,
         );
-        self.literal_memory_end += name.len() as u32;
+        self.literal_memory_end += len;
+        self.stats.literal_bytes += len;
 
-        // Save the offset in the literal memory for this identifier
+        // Save the offset in the literal memory for this literal
         self.literal_memory_offset.insert(entry, offset);
 
         Ok((offset, len))
@@ -689,19 +1113,11 @@ impl WasmGenerator {
                 )))
             }
         };
-        let memory = self.get_memory()?;
-        let offset = self.literal_memory_end;
-        let len = data.len() as u32;
-        self.module.data.add(
-            DataKind::Active(ActiveData {
-                memory,
-                location: walrus::ActiveDataLocation::Absolute(offset),
-            }),
-            data.clone(),
-        );
-        self.literal_memory_end += data.len() as u32;
-
-        Ok((offset, len))
+        let mut key = Vec::new();
+        value.serialize_write(&mut key).map_err(|e| {
+            GeneratorError::InternalError(format!("failed to serialize literal: {e:?}"))
+        })?;
+        self.intern_literal(LiteralMemoryEntry::Value(key), data)
     }
 
     pub(crate) fn block_from_expr(
@@ -746,32 +1162,45 @@ impl WasmGenerator {
             (false, false) => unreachable!("must include either repr or value"),
         };
 
-        // Save the offset (current stack pointer) into a local
+        // The space is reserved in the function's frame, which is allocated
+        // once in the function prelude, so the local is at a constant offset
+        // from the frame pointer.
+        // (local.set $offset (i32.add (local.get $frame-pointer) (i32.const <frame_size>))
         let offset = self.module.locals.add(ValType::I32);
         builder
             // []
-            .global_get(self.stack_pointer)
-            // [ stack_ptr ]
-            .local_tee(offset);
-        // [ stack_ptr ]
-
-        // TODO: The frame stack size can be computed at compile time, so we
-        //       should be able to increment the stack pointer once in the function
-        //       prelude with a constant instead of incrementing it for each local.
-        // (global.set $stack-pointer (i32.add (global.get $stack-pointer) (i32.const <size>))
-        builder
-            // [ stack_ptr ]
-            .i32_const(size)
-            // [ stack_ptr, size ]
+            .local_get(self.frame_pointer)
+            // [ frame_ptr ]
+            .i32_const(self.frame_size)
+            // [ frame_ptr, frame_size ]
             .binop(BinaryOp::I32Add)
-            // [ new_stack_ptr ]
-            .global_set(self.stack_pointer);
+            // [ offset ]
+            .local_set(offset);
         // [  ]
         self.frame_size += size;
 
         (offset, size)
     }
 
+    /// Reserves space for a value of type `ty` in the deploy-time scratch
+    /// region, for payloads which the host only reads while `.top-level`
+    /// runs, like the initial values of data vars. The region is part of the
+    /// `.top-level` frame, at the bottom of the call stack, so it takes no
+    /// space in the literal memory, and later calls reuse it as stack space.
+    pub(crate) fn create_deploy_scratch_local(
+        &mut self,
+        builder: &mut InstrSeqBuilder,
+        ty: &TypeSignature,
+    ) -> Result<LocalId, GeneratorError> {
+        if self.early_return_block_id.is_some() {
+            return Err(GeneratorError::InternalError(
+                "deploy-time scratch space is only available in .top-level".to_owned(),
+            ));
+        }
+        let (offset, _size) = self.create_call_stack_local(builder, ty, true, false);
+        Ok(offset)
+    }
+
     /// Write the value that is on the top of the data stack, which has type
     /// `ty`, to the memory, at offset stored in local variable,
     /// `offset_local`, plus constant offset `offset`. Returns the number of
@@ -1194,18 +1623,23 @@ impl WasmGenerator {
                     Ok(true)
                 }
                 NativeVariables::Regtest => {
-                    // Call the host interface function, `is_in_regtest`
-                    builder.call(self.func_by_name("stdlib.is_in_regtest"));
+                    match self.network {
+                        Some(network) => builder.i32_const(network.is_in_regtest() as i32),
+                        // Call the host interface function, `is_in_regtest`
+                        None => builder.call(self.func_by_name("stdlib.is_in_regtest")),
+                    };
                     Ok(true)
                 }
                 NativeVariables::Mainnet => {
-                    // Call the host interface function, `is_in_mainnet`
-                    builder.call(self.func_by_name("stdlib.is_in_mainnet"));
+                    self.is_in_mainnet(builder);
                     Ok(true)
                 }
                 NativeVariables::ChainId => {
-                    // Call the host interface function, `chain_id`
-                    builder.call(self.func_by_name("stdlib.chain_id"));
+                    match self.network {
+                        Some(network) => builder.i64_const(network.chain_id as i64).i64_const(0),
+                        // Call the host interface function, `chain_id`
+                        None => builder.call(self.func_by_name("stdlib.chain_id")),
+                    };
                     Ok(true)
                 }
             }
@@ -1283,6 +1717,19 @@ impl WasmGenerator {
         locals
     }
 
+    /// Name the locals holding the Wasm representation of the Clarity
+    /// variable `name`, so that they show up in the name section of the
+    /// module. Variables lowered to several locals get an index suffix.
+    pub(crate) fn name_locals(&mut self, name: &str, locals: &[LocalId]) {
+        if let [local] = locals {
+            self.module.locals.get_mut(*local).name = Some(name.to_owned());
+        } else {
+            for (i, local) in locals.iter().enumerate() {
+                self.module.locals.get_mut(*local).name = Some(format!("{name}.{i}"));
+            }
+        }
+    }
+
     pub fn func_by_name(&self, name: &str) -> FunctionId {
         self.module
             .funcs
@@ -1341,6 +1788,22 @@ impl WasmGenerator {
         }
     }
 
+    /// Emits `value`, the result of an expression folded at compile time.
+    fn visit_constant_value(
+        &mut self,
+        builder: &mut InstrSeqBuilder,
+        expr: &SymbolicExpression,
+        value: &clarity::vm::Value,
+    ) -> Result<(), GeneratorError> {
+        match value {
+            clarity::vm::Value::Bool(b) => {
+                builder.i32_const(*b as i32);
+                Ok(())
+            }
+            _ => self.visit_literal_value(builder, expr, value),
+        }
+    }
+
     fn visit_atom(
         &mut self,
         builder: &mut InstrSeqBuilder,
@@ -1447,10 +1910,8 @@ impl WasmGenerator {
             // Reserve space to store the returned value.
             let offset = self.module.locals.add(ValType::I32);
             builder.global_get(self.stack_pointer).local_tee(offset);
-            builder
-                .local_get(result_length)
-                .binop(BinaryOp::I32Add)
-                .global_set(self.stack_pointer);
+            builder.local_get(result_length).binop(BinaryOp::I32Add);
+            self.set_stack_pointer(builder);
 
             let memory = self.get_memory()?;
 
@@ -1607,12 +2068,19 @@ impl WasmGenerator {
 mod misc_tests {
     use std::env;
 
+    use clarity::types::StacksEpochId;
+    use clarity::vm::costs::LimitedCostTracker;
+    use clarity::vm::database::MemoryBackingStore;
+    use clarity::vm::types::QualifiedContractIdentifier;
+    use clarity::vm::ClarityVersion;
     use walrus::Module;
 
     // Tests that don't relate to specific words
     use crate::{
+        compile, compile_with_options,
         tools::{crosscheck, evaluate},
         wasm_generator::END_OF_STANDARD_DATA,
+        CompileOptions, Network, TargetNetwork,
     };
 
     #[test]
@@ -1724,4 +2192,220 @@ mod misc_tests {
             evaluate("(ok true)"),
         );
     }
+
+    #[test]
+    fn literals_are_reused() {
+        let standard = "'S1G2081040G2081040G2081040G208105NK8PE5";
+        let contract = "'S1G2081040G2081040G2081040G208105NK8PE5.other";
+        let snippet = format!(
+            "
+(define-read-only (a) (list 0x0102030405 0x0102030405))
+(define-read-only (b) (list {standard} {standard}))
+(define-read-only (c) (list {contract} {contract}))
+"
+        );
+        let mut datastore = MemoryBackingStore::new();
+        let stats = compile(
+            &snippet,
+            &QualifiedContractIdentifier::transient(),
+            LimitedCostTracker::new_free(),
+            ClarityVersion::latest(),
+            StacksEpochId::latest(),
+            &mut datastore.as_analysis_db(),
+        )
+        .expect("Failed to compile contract.")
+        .stats;
+
+        // The second uses of the buffer (5 bytes), the standard principal
+        // (22 bytes) and the contract principal (27 bytes) are saved.
+        assert_eq!(stats.literal_bytes_saved, 5 + 22 + 27);
+    }
+
+    #[test]
+    fn network_constants_replace_host_imports() {
+        let snippet = "
+(define-read-only (network)
+  { mainnet: is-in-mainnet, regtest: is-in-regtest, chain: chain-id,
+    standard: (is-standard 'SP000000000000000000002Q6VF78) })
+";
+        let network_imports = |network| {
+            let mut datastore = MemoryBackingStore::new();
+            let module = compile_with_options(
+                snippet,
+                &QualifiedContractIdentifier::transient(),
+                LimitedCostTracker::new_free(),
+                ClarityVersion::latest(),
+                StacksEpochId::latest(),
+                &mut datastore.as_analysis_db(),
+                &CompileOptions {
+                    network,
+                    ..CompileOptions::default()
+                },
+            )
+            .expect("Failed to compile contract.")
+            .module;
+            let mut imports: Vec<_> = module
+                .imports
+                .iter()
+                .map(|import| import.name.clone())
+                .filter(|name| {
+                    ["is_in_mainnet", "is_in_regtest", "chain_id"].contains(&name.as_str())
+                })
+                .collect();
+            imports.sort();
+            imports
+        };
+
+        assert_eq!(
+            network_imports(None),
+            vec!["chain_id", "is_in_mainnet", "is_in_regtest"]
+        );
+        assert!(network_imports(Some(TargetNetwork::new(Network::Mainnet))).is_empty());
+        assert!(network_imports(Some(TargetNetwork {
+            network: Network::Regtest,
+            chain_id: 0x1234,
+        }))
+        .is_empty());
+    }
+
+    #[test]
+    fn network_constants_are_baked_in() {
+        use wasmtime::{Engine, Linker, Store, Val};
+
+        let snippet = "
+(define-read-only (mainnet) is-in-mainnet)
+(define-read-only (regtest) is-in-regtest)
+(define-read-only (chain) chain-id)
+(define-read-only (standard) (is-standard 'ST000000000000000000002AMW42H))
+(define-read-only (mainnet-standard) (is-standard 'SP000000000000000000002Q6VF78))
+";
+        let mut datastore = MemoryBackingStore::new();
+        let wasm = compile_with_options(
+            snippet,
+            &QualifiedContractIdentifier::transient(),
+            LimitedCostTracker::new_free(),
+            ClarityVersion::latest(),
+            StacksEpochId::latest(),
+            &mut datastore.as_analysis_db(),
+            &CompileOptions {
+                network: Some(TargetNetwork {
+                    network: Network::Regtest,
+                    chain_id: 0x1234,
+                }),
+                ..CompileOptions::default()
+            },
+        )
+        .expect("Failed to compile contract.")
+        .module
+        .emit_wasm();
+
+        // The host functions are not needed, any call to them traps
+        let engine = Engine::default();
+        let module = wasmtime::Module::new(&engine, wasm).expect("Failed to load module.");
+        let mut linker = Linker::new(&engine);
+        linker
+            .define_unknown_imports_as_traps(&module)
+            .expect("Failed to define imports.");
+        let mut store = Store::new(&engine, ());
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .expect("Failed to instantiate module.");
+
+        let mut call = |name: &str| {
+            let func = instance
+                .get_func(&mut store, name)
+                .unwrap_or_else(|| panic!("Missing function {name}."));
+            let mut results = vec![Val::I32(0); func.ty(&store).results().len()];
+            func.call(&mut store, &[], &mut results)
+                .unwrap_or_else(|e| panic!("Failed to call {name}: {e}"));
+            results
+        };
+        assert_eq!(call("mainnet")[0].i32(), Some(0));
+        assert_eq!(call("regtest")[0].i32(), Some(1));
+        let chain = call("chain");
+        assert_eq!((chain[0].i64(), chain[1].i64()), (Some(0x1234), Some(0)));
+        assert_eq!(call("standard")[0].i32(), Some(1));
+        assert_eq!(call("mainnet-standard")[0].i32(), Some(0));
+    }
+
+    #[test]
+    fn memory_grows_for_large_buffers() {
+        // The padded buffer is written in the call stack, beyond the memory
+        // sized from the literals and the stack frames.
+        let buffer = "ab".repeat(200_000);
+        crate::tools::crosscheck_compare_only(&format!(
+            "
+(define-private (hash (data (buff 200000)))
+  (sha256 data))
+(hash 0x{buffer})
+"
+        ));
+    }
+
+    #[test]
+    fn memory_grows_for_large_map_results() {
+        // Each byte of the buffer becomes a 16-byte `uint`, so the result of
+        // `map`, reserved on the call stack, is far beyond the initial memory.
+        let buffer = "ab".repeat(20_000);
+        crate::tools::crosscheck_compare_only(&format!(
+            "
+(define-private (widen (byte (buff 1)))
+  (buff-to-uint-be byte))
+(define-private (widen-all (data (buff 20000)))
+  (map widen data))
+(len (widen-all 0x{buffer}))
+"
+        ));
+    }
+
+    #[test]
+    fn memory_is_limited_to_max_pages() {
+        let compile_limited = |snippet: &str, max_memory_pages| {
+            let mut datastore = MemoryBackingStore::new();
+            compile_with_options(
+                snippet,
+                &QualifiedContractIdentifier::transient(),
+                LimitedCostTracker::new_free(),
+                ClarityVersion::latest(),
+                StacksEpochId::latest(),
+                &mut datastore.as_analysis_db(),
+                &CompileOptions {
+                    max_memory_pages,
+                    ..CompileOptions::default()
+                },
+            )
+            .map(|result| result.module)
+        };
+
+        let module = compile_limited("(define-read-only (foo) (concat \"a\" \"b\"))", Some(20))
+            .expect("Failed to compile contract.");
+        let memory = module.memories.iter().next().unwrap();
+        assert_eq!(memory.maximum, Some(20));
+        assert!(memory.initial <= 20);
+
+        let module = compile_limited("(define-read-only (foo) u1)", None)
+            .expect("Failed to compile contract.");
+        assert_eq!(module.memories.iter().next().unwrap().maximum, None);
+
+        // The literal memory must fit in the limit.
+        let literal = "a".repeat(200_000);
+        assert!(compile_limited(&format!("(define-constant big \"{literal}\")"), Some(2)).is_err());
+    }
+
+    #[test]
+    fn function_frames_do_not_overlap() {
+        crosscheck(
+            "
+(define-map squares uint uint)
+(define-private (store (k uint))
+  (map-set squares k (* k k)))
+(define-public (foo)
+  (begin
+    (map store (list u1 u2 u3))
+    (ok (list (map-get? squares u1) (map-get? squares u2) (map-get? squares u3)))))
+(foo)
+",
+            evaluate("(ok (list (some u1) (some u4) (some u9)))"),
+        );
+    }
 }
@@ -49,6 +49,7 @@ impl ComplexWord for Let {
                 })?
                 .clone();
             let locals = generator.save_to_locals(builder, &ty, true);
+            generator.name_locals(name.as_str(), &locals);
 
             // Add these named locals to the map
             generator.bindings.insert(name.to_string(), locals);
@@ -480,7 +480,7 @@ impl ComplexWord for Unwrap {
         &self,
         generator: &mut WasmGenerator,
         builder: &mut walrus::InstrSeqBuilder,
-        _expr: &SymbolicExpression,
+        expr: &SymbolicExpression,
         args: &[SymbolicExpression],
     ) -> Result<(), GeneratorError> {
         let input = args.get_expr(0)?;
@@ -522,7 +522,7 @@ impl ComplexWord for Unwrap {
             generator.set_expr_type(throw, return_ty.clone())?;
         }
         generator.traverse_expr(&mut throw_branch, throw)?;
-        generator.return_early(&mut throw_branch)?;
+        generator.return_early(&mut throw_branch, expr)?;
 
         let throw_branch_id = throw_branch.id();
 
@@ -561,7 +561,7 @@ impl ComplexWord for UnwrapErr {
         &self,
         generator: &mut WasmGenerator,
         builder: &mut walrus::InstrSeqBuilder,
-        _expr: &SymbolicExpression,
+        expr: &SymbolicExpression,
         args: &[SymbolicExpression],
     ) -> Result<(), GeneratorError> {
         let input = args.get_expr(0)?;
@@ -605,7 +605,7 @@ impl ComplexWord for UnwrapErr {
             generator.set_expr_type(throw, return_ty.clone())?;
         }
         generator.traverse_expr(&mut throw_branch, throw)?;
-        generator.return_early(&mut throw_branch)?;
+        generator.return_early(&mut throw_branch, expr)?;
 
         let throw_branch_id = throw_branch.id();
 
@@ -650,7 +650,7 @@ impl ComplexWord for Asserts {
         &self,
         generator: &mut WasmGenerator,
         builder: &mut walrus::InstrSeqBuilder,
-        _expr: &SymbolicExpression,
+        expr: &SymbolicExpression,
         args: &[SymbolicExpression],
     ) -> Result<(), GeneratorError> {
         let input = args.get_expr(0)?;
@@ -693,7 +693,7 @@ impl ComplexWord for Asserts {
             generator.set_expr_type(throw, return_ty.clone())?;
         }
         generator.traverse_expr(&mut throw_branch, throw)?;
-        generator.return_early(&mut throw_branch)?;
+        generator.return_early(&mut throw_branch, expr)?;
 
         let throw_branch_id = throw_branch.id();
 
@@ -720,7 +720,7 @@ impl ComplexWord for Try {
         &self,
         generator: &mut WasmGenerator,
         builder: &mut walrus::InstrSeqBuilder,
-        _expr: &SymbolicExpression,
+        expr: &SymbolicExpression,
         args: &[SymbolicExpression],
     ) -> Result<(), GeneratorError> {
         let input = args.get_expr(0)?;
@@ -752,7 +752,7 @@ impl ComplexWord for Try {
                     None => &TypeSignature::NoType,
                 };
                 add_placeholder_for_clarity_type(&mut throw_branch, placeholder_ty);
-                generator.return_early(&mut throw_branch)?;
+                generator.return_early(&mut throw_branch, expr)?;
 
                 let throw_branch_id = throw_branch.id();
 
@@ -804,7 +804,7 @@ impl ComplexWord for Try {
                 for local in &err_locals {
                     throw_branch.local_get(*local);
                 }
-                generator.return_early(&mut throw_branch)?;
+                generator.return_early(&mut throw_branch, expr)?;
 
                 let throw_branch_id = throw_branch.id();
 
@@ -42,6 +42,7 @@ impl ComplexWord for ToConsensusBuff {
             .local_set(offset);
 
         // Write the serialized value to the top of the call stack
+        generator.ensure_serialization_memory(builder, offset, &ty)?;
         generator.serialize_to_memory(builder, offset, 0, &ty)?;
 
         builder.local_set(length);
@@ -64,8 +65,8 @@ impl ComplexWord for ToConsensusBuff {
                 |then| {
                     then.local_get(offset)
                         .local_get(length)
-                        .binop(walrus::ir::BinaryOp::I32Add)
-                        .global_set(generator.stack_pointer);
+                        .binop(walrus::ir::BinaryOp::I32Add);
+                    generator.set_stack_pointer(then);
 
                     then.i32_const(1).local_get(offset).local_get(length);
                 },
@@ -1,5 +1,7 @@
+use clarity::vm::clarity_wasm::get_type_size;
 use clarity::vm::types::PrincipalData;
 use clarity::vm::{ClarityName, SymbolicExpression, SymbolicExpressionType, Value};
+use walrus::ir::BinaryOp;
 use walrus::ValType;
 
 use super::ComplexWord;
@@ -58,7 +60,6 @@ impl ComplexWord for ContractCall {
         {
             // This is a static contract call.
             // Push the contract identifier onto the stack
-            // TODO(#111): These should be tracked for reuse, similar to the string literals
             let (id_offset, id_length) =
                 generator.add_literal(&contract_identifier.clone().into())?;
             builder
@@ -80,24 +81,32 @@ impl ComplexWord for ContractCall {
             .i32_const(fn_offset as i32)
             .i32_const(fn_length as i32);
 
-        // Write the arguments to the call stack, to be read by the host
+        let arg_types = args
+            .iter()
+            .map(|arg| {
+                generator.get_expr_type(arg).cloned().ok_or_else(|| {
+                    GeneratorError::TypeError("contract-call? argument must be typed".to_owned())
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Reserve space on the call stack for the arguments, to be read by
+        // the host
         let arg_offset = generator.module.locals.add(ValType::I32);
         builder
             .global_get(generator.stack_pointer)
-            .local_set(arg_offset);
+            .local_tee(arg_offset)
+            .i32_const(arg_types.iter().map(get_type_size).sum::<i32>())
+            .binop(BinaryOp::I32Add);
+        generator.set_stack_pointer(builder);
+
+        // Write the arguments to the reserved space
         let mut arg_length = 0;
-        for arg in args {
+        for (arg, arg_ty) in args.iter().zip(&arg_types) {
             // Traverse the argument, pushing it onto the stack
             generator.traverse_expr(builder, arg)?;
 
-            let arg_ty = generator
-                .get_expr_type(arg)
-                .ok_or_else(|| {
-                    GeneratorError::TypeError("contract-call? argument must be typed".to_owned())
-                })?
-                .clone();
-
-            arg_length += generator.write_to_memory(builder, arg_offset, arg_length, &arg_ty)?;
+            arg_length += generator.write_to_memory(builder, arg_offset, arg_length, arg_ty)?;
         }
 
         // Push the arguments offset and length onto the data stack
@@ -3,20 +3,7 @@ use clarity::vm::{ClarityName, SymbolicExpression};
 use walrus::ir::{IfElse, UnaryOp};
 
 use super::ComplexWord;
-use crate::wasm_generator::{drop_value, ArgumentsExt, GeneratorError, WasmGenerator};
-
-/// `Trap` should match the values used in the standard library and is used to
-/// indicate the reason for a runtime error from the Clarity code.
-#[allow(dead_code)]
-#[repr(i32)]
-enum Trap {
-    Overflow = 0,
-    Underflow = 1,
-    DivideByZero = 2,
-    LogOfNumberLessThanOrEqualToZero = 3,
-    ExpectedANonNegativeNumber = 4,
-    Panic = 5,
-}
+use crate::wasm_generator::{drop_value, ArgumentsExt, ErrorCode, GeneratorError, WasmGenerator};
 
 #[derive(Debug)]
 pub struct Begin;
@@ -58,7 +45,7 @@ impl ComplexWord for UnwrapPanic {
         &self,
         generator: &mut WasmGenerator,
         builder: &mut walrus::InstrSeqBuilder,
-        _expr: &SymbolicExpression,
+        expr: &SymbolicExpression,
         args: &[SymbolicExpression],
     ) -> Result<(), GeneratorError> {
         let input = args.get_expr(0)?;
@@ -92,17 +79,7 @@ impl ComplexWord for UnwrapPanic {
                 // If the indicator is 0, throw a runtime error
                 let if_id = {
                     let mut if_case = builder.dangling_instr_seq(None);
-                    if_case.i32_const(Trap::Panic as i32).call(
-                        generator
-                            .module
-                            .funcs
-                            .by_name("stdlib.runtime-error")
-                            .ok_or_else(|| {
-                                GeneratorError::InternalError(
-                                    "stdlib.runtime-error not found".to_owned(),
-                                )
-                            })?,
-                    );
+                    generator.runtime_error(&mut if_case, ErrorCode::Panic, expr)?;
                     if_case.id()
                 };
 
@@ -146,17 +123,7 @@ impl ComplexWord for UnwrapPanic {
                 // If the indicator is 0, throw a runtime error
                 let if_id = {
                     let mut if_case = builder.dangling_instr_seq(None);
-                    if_case.i32_const(Trap::Panic as i32).call(
-                        generator
-                            .module
-                            .funcs
-                            .by_name("stdlib.runtime-error")
-                            .ok_or_else(|| {
-                                GeneratorError::InternalError(
-                                    "stdlib.runtime-error not found".to_owned(),
-                                )
-                            })?,
-                    );
+                    generator.runtime_error(&mut if_case, ErrorCode::Panic, expr)?;
                     if_case.id()
                 };
 
@@ -194,7 +161,7 @@ impl ComplexWord for UnwrapErrPanic {
         &self,
         generator: &mut WasmGenerator,
         builder: &mut walrus::InstrSeqBuilder,
-        _expr: &SymbolicExpression,
+        expr: &SymbolicExpression,
         args: &[SymbolicExpression],
     ) -> Result<(), GeneratorError> {
         let input = args.get_expr(0)?;
@@ -242,17 +209,7 @@ impl ComplexWord for UnwrapErrPanic {
 
                 let else_id = {
                     let mut else_case = builder.dangling_instr_seq(None);
-                    else_case.i32_const(Trap::Panic as i32).call(
-                        generator
-                            .module
-                            .funcs
-                            .by_name("stdlib.runtime-error")
-                            .ok_or_else(|| {
-                                GeneratorError::InternalError(
-                                    "stdlib.runtime-error not found".to_owned(),
-                                )
-                            })?,
-                    );
+                    generator.runtime_error(&mut else_case, ErrorCode::Panic, expr)?;
                     else_case.id()
                 };
 
@@ -1,5 +1,4 @@
 use clarity::vm::{ClarityName, SymbolicExpression};
-use walrus::ValType;
 
 use super::ComplexWord;
 use crate::wasm_generator::{ArgumentsExt, GeneratorError, LiteralMemoryEntry, WasmGenerator};
@@ -30,28 +29,19 @@ impl ComplexWord for DefineDataVar {
         // data stack)
         generator.traverse_expr(builder, initial)?;
 
-        // The initial value can be placed on the top of the memory, since at
-        // the top-level, we have not set up the call stack yet.
+        // The initial value is only read by the host when .top-level is
+        // called, so it is placed in the deploy-time scratch region.
         let ty = generator
             .get_expr_type(initial)
             .ok_or_else(|| {
                 GeneratorError::TypeError("initial value expression must be typed".to_owned())
             })?
             .clone();
-        let offset = generator.module.locals.add(ValType::I32);
-        builder
-            .i32_const(generator.literal_memory_end as i32)
-            .local_set(offset);
+        let offset = generator.create_deploy_scratch_local(builder, &ty)?;
 
         // Write the initial value to the memory, to be read by the host.
         let size = generator.write_to_memory(builder, offset, 0, &ty)?;
 
-        // Increment the literal memory end
-        // FIXME: These initial values do not need to be saved in the literal
-        //        memory forever... we just need them once, when .top-level
-        //        is called.
-        generator.literal_memory_end += size;
-
         // Push the name onto the data stack
         builder
             .i32_const(name_offset as i32)
@@ -218,8 +208,40 @@ impl ComplexWord for GetDataVar {
 
 #[cfg(test)]
 mod tests {
+    use clarity::types::StacksEpochId;
+    use clarity::vm::costs::LimitedCostTracker;
+    use clarity::vm::database::MemoryBackingStore;
+    use clarity::vm::types::QualifiedContractIdentifier;
+    use clarity::vm::ClarityVersion;
+
+    use crate::compile;
     use crate::tools::{crosscheck, evaluate};
 
+    /// The initial value of the stack pointer, i.e. the end of the literal
+    /// memory, of the compiled `snippet`.
+    fn literal_memory_end(snippet: &str) -> i32 {
+        let mut datastore = MemoryBackingStore::new();
+        let module = compile(
+            snippet,
+            &QualifiedContractIdentifier::transient(),
+            LimitedCostTracker::new_free(),
+            ClarityVersion::latest(),
+            StacksEpochId::latest(),
+            &mut datastore.as_analysis_db(),
+        )
+        .expect("Failed to compile contract.")
+        .module;
+        let stack_pointer = module
+            .globals
+            .iter()
+            .find(|global| global.name.as_deref() == Some("stack-pointer"))
+            .expect("Missing stack pointer.");
+        match stack_pointer.kind {
+            walrus::GlobalKind::Local(walrus::InitExpr::Value(walrus::ir::Value::I32(end))) => end,
+            _ => panic!("Unexpected stack pointer initializer."),
+        }
+    }
+
     #[test]
     fn test_var_get() {
         crosscheck(
@@ -251,4 +273,31 @@ mod tests {
             evaluate("(ok 5368002525449479521366)"),
         );
     }
+
+    #[test]
+    fn test_var_tuple_initial_value() {
+        crosscheck(
+            "
+(define-data-var point { x: int, y: int, z: int } { x: 1, y: 2, z: 3 })
+
+(define-public (simple)
+  (begin
+    (var-set point (merge (var-get point) { z: 4 }))
+    (ok (var-get point))))
+
+(simple)
+",
+            evaluate("(ok { x: 1, y: 2, z: 4 })"),
+        );
+    }
+
+    #[test]
+    fn initial_values_are_not_in_literal_memory() {
+        assert_eq!(
+            literal_memory_end("(define-data-var v int 1)"),
+            literal_memory_end(
+                "(define-data-var v { a: int, b: int, c: int, d: int } { a: 1, b: 2, c: 3, d: 4 })"
+            )
+        );
+    }
 }
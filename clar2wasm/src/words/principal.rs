@@ -48,10 +48,8 @@ impl SimpleWord for IsStandard {
         let version_local = generator.module.locals.add(ValType::I32);
         builder.local_tee(version_local);
 
-        // TODO: It would be nice if this was a global variable that gets set
-        //       at compile time, instead of requiring a host-interface call.
         // Check if we are in mainnet (leaves a boolean on the stack)
-        builder.call(generator.func_by_name("stdlib.is_in_mainnet"));
+        generator.is_in_mainnet(builder);
 
         builder.if_else(
             InstrSeqType::new(
@@ -282,8 +280,8 @@ impl ComplexWord for PrincipalOf {
         builder
             .global_get(generator.stack_pointer)
             .i32_const(STANDARD_PRINCIPAL_BYTES as i32)
-            .binop(BinaryOp::I32Add)
-            .global_set(generator.stack_pointer);
+            .binop(BinaryOp::I32Add);
+        generator.set_stack_pointer(builder);
 
         // Call the host interface function, `principal-of?`
         builder.call(generator.func_by_name("stdlib.principal_of"));
@@ -54,6 +54,7 @@ impl ComplexWord for Print {
         }
 
         // Write the serialized value to the top of the call stack
+        generator.ensure_serialization_memory(builder, offset, &ty)?;
         generator.serialize_to_memory(builder, offset, 0, &ty)?;
 
         // Save the length to a local
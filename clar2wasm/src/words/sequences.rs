@@ -628,7 +628,7 @@ impl ComplexWord for Map {
         // [ stack_pointer, output_size ]
         builder.binop(ir::BinaryOp::I32Add);
         // [ end_offset ]
-        builder.global_set(generator.stack_pointer);
+        generator.set_stack_pointer(builder);
         // [ ]
 
         // Create an index to count the number of elements to loop over.
@@ -1,8 +1,13 @@
-use clar2wasm::wasm_generator::END_OF_STANDARD_DATA;
+use clar2wasm::wasm_generator::{ErrorCode, END_OF_STANDARD_DATA};
+use clarity::types::StacksEpochId;
+use clarity::vm::costs::LimitedCostTracker;
+use clarity::vm::database::MemoryBackingStore;
+use clarity::vm::types::QualifiedContractIdentifier;
+use clarity::vm::ClarityVersion;
 use hex::FromHex;
 use wasmtime::Val;
 
-use crate::utils::load_stdlib;
+use crate::utils::{load_module, load_stdlib, load_stdlib_from, STANDARD_LIB};
 
 #[test]
 fn test_add_uint() {
@@ -60,6 +65,130 @@ fn test_add_uint() {
     .expect_err("expected overflow");
 }
 
+#[test]
+fn test_runtime_error_code() {
+    let (instance, mut store) = load_stdlib().unwrap();
+    let code = instance
+        .get_global(&mut store, "runtime-error-code")
+        .unwrap();
+    assert_eq!(code.get(&mut store).i32(), Some(-1));
+
+    let add = instance.get_func(&mut store, "stdlib.add-uint").unwrap();
+    let mut sum = [Val::I64(0), Val::I64(0)];
+    add.call(
+        &mut store,
+        &[Val::I64(-1), Val::I64(-1), Val::I64(1), Val::I64(0)],
+        &mut sum,
+    )
+    .expect_err("expected overflow");
+    assert_eq!(code.get(&mut store).i32(), Some(ErrorCode::Overflow as i32));
+
+    let div = instance.get_func(&mut store, "stdlib.div-uint").unwrap();
+    let mut quotient = [Val::I64(0), Val::I64(0)];
+    div.call(
+        &mut store,
+        &[Val::I64(1), Val::I64(0), Val::I64(0), Val::I64(0)],
+        &mut quotient,
+    )
+    .expect_err("expected divide by zero");
+    assert_eq!(
+        code.get(&mut store).i32(),
+        Some(ErrorCode::DivideByZero as i32)
+    );
+}
+
+#[test]
+fn test_runtime_error_location() {
+    let mut datastore = MemoryBackingStore::new();
+    let wasm = clar2wasm::compile(
+        "(define-read-only (get-value (value (optional uint)))
+  (+ u1
+     (unwrap-panic value)))",
+        &QualifiedContractIdentifier::transient(),
+        LimitedCostTracker::new_free(),
+        ClarityVersion::latest(),
+        StacksEpochId::latest(),
+        &mut datastore.as_analysis_db(),
+    )
+    .expect("Failed to compile contract.")
+    .module
+    .emit_wasm();
+
+    let (instance, mut store) = load_module(wasm).unwrap();
+    let get_value = instance.get_func(&mut store, "get-value").unwrap();
+    let mut results = vec![Val::I32(0); get_value.ty(&store).results().len()];
+    // Call with `none`
+    get_value
+        .call(
+            &mut store,
+            &[Val::I32(0), Val::I64(0), Val::I64(0)],
+            &mut results,
+        )
+        .expect_err("expected panic");
+
+    let mut global = |name: &str| {
+        instance
+            .get_global(&mut store, name)
+            .unwrap()
+            .get(&mut store)
+            .i32()
+    };
+    assert_eq!(global("runtime-error-code"), Some(ErrorCode::Panic as i32));
+    assert_eq!(global("runtime-error-start-line"), Some(3));
+    assert_eq!(global("runtime-error-start-column"), Some(6));
+    assert_eq!(global("runtime-error-end-line"), Some(3));
+    assert_eq!(global("runtime-error-end-column"), Some(25));
+}
+
+#[test]
+fn test_ensure_memory() {
+    let (instance, mut store) = load_stdlib().unwrap();
+    let memory = instance.get_memory(&mut store, "memory").unwrap();
+    let ensure = instance
+        .get_func(&mut store, "stdlib.ensure-memory")
+        .unwrap();
+
+    // Already in the memory
+    ensure
+        .call(&mut store, &[Val::I32(10 * 65536)], &mut [])
+        .expect("call to ensure-memory failed");
+    assert_eq!(memory.size(&store), 10);
+
+    // Grows up to the page containing the end
+    ensure
+        .call(&mut store, &[Val::I32(12 * 65536 + 1)], &mut [])
+        .expect("call to ensure-memory failed");
+    assert_eq!(memory.size(&store), 13);
+}
+
+#[test]
+fn test_ensure_memory_limit() {
+    let (instance, mut store) = load_stdlib_from(&STANDARD_LIB.replace(
+        r#"(memory (export "memory") 10)"#,
+        r#"(memory (export "memory") 10 12)"#,
+    ))
+    .unwrap();
+    let code = instance
+        .get_global(&mut store, "runtime-error-code")
+        .unwrap();
+    let ensure = instance
+        .get_func(&mut store, "stdlib.ensure-memory")
+        .unwrap();
+
+    ensure
+        .call(&mut store, &[Val::I32(12 * 65536)], &mut [])
+        .expect("call to ensure-memory failed");
+    assert_eq!(code.get(&mut store).i32(), Some(-1));
+
+    ensure
+        .call(&mut store, &[Val::I32(12 * 65536 + 1)], &mut [])
+        .expect_err("expected memory limit exceeded");
+    assert_eq!(
+        code.get(&mut store).i32(),
+        Some(ErrorCode::MemoryLimitExceeded as i32)
+    );
+}
+
 #[test]
 fn test_add_int() {
     let (instance, mut store) = load_stdlib().unwrap();
@@ -9,7 +9,25 @@ use wasmtime::{Caller, Engine, Instance, Linker, Module, Store, Val};
 /// Load the standard library into a Wasmtime instance. This is used to load in
 /// the standard.wat file and link in all of the host interface functions.
 pub(crate) fn load_stdlib() -> Result<(Instance, Store<()>), wasmtime::Error> {
-    let standard_lib = include_str!("../../src/standard/standard.wat");
+    load_stdlib_from(STANDARD_LIB)
+}
+
+/// The source of the standard library.
+pub(crate) const STANDARD_LIB: &str = include_str!("../../src/standard/standard.wat");
+
+/// Load `standard_lib`, a variant of the standard library source, like
+/// `load_stdlib`.
+pub(crate) fn load_stdlib_from(
+    standard_lib: &str,
+) -> Result<(Instance, Store<()>), wasmtime::Error> {
+    load_module(standard_lib)
+}
+
+/// Load `module`, in text or binary format, like `load_stdlib`. This is used
+/// to run compiled contracts, which embed the standard library.
+pub(crate) fn load_module(
+    module: impl AsRef<[u8]>,
+) -> Result<(Instance, Store<()>), wasmtime::Error> {
     let engine = Engine::default();
     let mut store = Store::new(&engine, ());
 
@@ -591,7 +609,7 @@ pub(crate) fn load_stdlib() -> Result<(Instance, Store<()>), wasmtime::Error> {
         })
         .unwrap();
 
-    let module = Module::new(&engine, standard_lib).unwrap();
+    let module = Module::new(&engine, module).unwrap();
     let instance = linker.instantiate(&mut store, &module)?;
     Ok((instance, store))
 }
@@ -10,6 +10,7 @@ pub mod default_to;
 pub mod equal;
 pub mod noop;
 pub mod optional;
+pub mod programs;
 pub mod regression;
 pub mod response;
 pub mod sequences;
@@ -0,0 +1,381 @@
+//! Generators of whole well-typed Clarity contracts, with nested `let` and
+//! `match`, `fold` and `map` over private functions, data-vars, maps and a
+//! fungible token, to crosscheck programs rather than single words. The
+//! `fuzz` example runs the same generator for as long as needed.
+
+use clar2wasm::tools::{crosscheck_report, Divergence};
+use proptest::prelude::*;
+use proptest::sample::select;
+use proptest::strategy::BoxedStrategy;
+
+/// Maximum depth of the generated expressions.
+const MAX_DEPTH: u32 = 4;
+/// Number of private functions generated for `fold`, and for `map`.
+const HELPERS: usize = 2;
+
+/// The types of the generated expressions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ty {
+    Int,
+    UInt,
+    Bool,
+    /// `(optional int)`
+    OptionalInt,
+    /// `(response bool uint)`, the type of `ft-mint?` and `ft-burn?`
+    Response,
+    /// `(list n int)`
+    ListInt,
+}
+
+const ALL_TYPES: [Ty; 6] = [
+    Ty::Int,
+    Ty::UInt,
+    Ty::Bool,
+    Ty::OptionalInt,
+    Ty::Response,
+    Ty::ListInt,
+];
+
+/// The expression forms, which are generated for the types they can return.
+#[derive(Debug, Clone, Copy)]
+enum Form {
+    If,
+    Let,
+    MatchOptional,
+    MatchResponse,
+    Arithmetic,
+    Fold,
+    VarGet,
+    MapGetDefault,
+    Len,
+    Balance,
+    Compare,
+    Logic,
+    Not,
+    IsEq,
+    IsSome,
+    IsOk,
+    VarSet,
+    MapSet,
+    MapDelete,
+    Some,
+    MapGet,
+    ElementAt,
+    OkOrErr,
+    Token,
+    List,
+    Map,
+}
+
+/// The variables in scope, and whether the private functions can be called.
+#[derive(Debug, Clone)]
+struct Scope {
+    vars: Vec<(String, Ty)>,
+    helpers: bool,
+}
+
+impl Scope {
+    fn with(&self, name: String, ty: Ty) -> Self {
+        let mut scope = self.clone();
+        scope.vars.push((name, ty));
+        scope
+    }
+}
+
+fn literal(ty: Ty) -> BoxedStrategy<String> {
+    match ty {
+        Ty::Int => prop_oneof![9 => -1000i128..1000, 1 => any::<i128>()]
+            .prop_map(|n| n.to_string())
+            .boxed(),
+        Ty::UInt => prop_oneof![9 => 0u128..1000, 1 => any::<u128>()]
+            .prop_map(|n| format!("u{n}"))
+            .boxed(),
+        Ty::Bool => any::<bool>().prop_map(|b| b.to_string()).boxed(),
+        Ty::OptionalInt => literal(Ty::Int).prop_map(|n| format!("(some {n})")).boxed(),
+        Ty::Response => (literal(Ty::Bool), literal(Ty::Bool), literal(Ty::UInt))
+            .prop_map(|(c, ok, err)| format!("(if {c} (ok {ok}) (err {err}))"))
+            .boxed(),
+        Ty::ListInt => prop::collection::vec(literal(Ty::Int), 1..4)
+            .prop_map(|items| format!("(list {})", items.join(" ")))
+            .boxed(),
+    }
+}
+
+/// A literal, or a variable of type `ty`.
+fn leaf(ty: Ty, scope: &Scope) -> BoxedStrategy<String> {
+    let vars: Vec<_> = scope
+        .vars
+        .iter()
+        .filter(|(_, var_ty)| *var_ty == ty)
+        .map(|(name, _)| name.clone())
+        .collect();
+    if vars.is_empty() {
+        literal(ty)
+    } else {
+        prop_oneof![literal(ty), select(vars)].boxed()
+    }
+}
+
+fn forms(ty: Ty, scope: &Scope) -> Vec<Form> {
+    let mut forms = vec![
+        Form::If,
+        Form::Let,
+        Form::MatchOptional,
+        Form::MatchResponse,
+    ];
+    forms.extend_from_slice(match ty {
+        Ty::Int => &[
+            Form::Arithmetic,
+            Form::VarGet,
+            Form::MapGetDefault,
+            Form::Fold,
+        ],
+        Ty::UInt => &[Form::Arithmetic, Form::VarGet, Form::Len, Form::Balance],
+        Ty::Bool => &[
+            Form::Compare,
+            Form::Logic,
+            Form::Not,
+            Form::IsEq,
+            Form::IsSome,
+            Form::IsOk,
+            Form::VarSet,
+            Form::MapSet,
+            Form::MapDelete,
+        ],
+        Ty::OptionalInt => &[Form::Some, Form::MapGet, Form::ElementAt],
+        Ty::Response => &[Form::OkOrErr, Form::Token],
+        Ty::ListInt => &[Form::List, Form::Map],
+    });
+    if !scope.helpers {
+        forms.retain(|form| !matches!(form, Form::Fold | Form::Map));
+    }
+    forms
+}
+
+/// An expression of type `ty`, nested at most `depth` times.
+fn expr(ty: Ty, scope: Scope, depth: u32) -> BoxedStrategy<String> {
+    let leaf = leaf(ty, &scope);
+    if depth == 0 {
+        return leaf;
+    }
+
+    // Only the chosen form builds the strategies of its operands.
+    let compound =
+        select(forms(ty, &scope)).prop_flat_map(move |form| build(form, ty, &scope, depth - 1));
+    prop_oneof![1 => leaf, 2 => compound].boxed()
+}
+
+fn build(form: Form, ty: Ty, scope: &Scope, depth: u32) -> BoxedStrategy<String> {
+    let sub = |ty: Ty| expr(ty, scope.clone(), depth);
+    match form {
+        Form::If => (sub(Ty::Bool), sub(ty), sub(ty))
+            .prop_map(|(c, a, b)| format!("(if {c} {a} {b})"))
+            .boxed(),
+        Form::Let => {
+            let scope = scope.clone();
+            select(ALL_TYPES.to_vec())
+                .prop_flat_map(move |binding_ty| {
+                    // Names cannot be shadowed, so they are unique per depth
+                    let name = format!("l{depth}");
+                    (
+                        expr(binding_ty, scope.clone(), depth),
+                        expr(ty, scope.with(name.clone(), binding_ty), depth),
+                    )
+                        .prop_map(move |(value, body)| format!("(let (({name} {value})) {body})"))
+                })
+                .boxed()
+        }
+        Form::MatchOptional => {
+            let name = format!("s{depth}");
+            (
+                sub(Ty::OptionalInt),
+                expr(ty, scope.with(name.clone(), Ty::Int), depth),
+                sub(ty),
+            )
+                .prop_map(move |(o, some, none)| format!("(match {o} {name} {some} {none})"))
+                .boxed()
+        }
+        Form::MatchResponse => {
+            let (ok_name, err_name) = (format!("o{depth}"), format!("e{depth}"));
+            (
+                sub(Ty::Response),
+                expr(ty, scope.with(ok_name.clone(), Ty::Bool), depth),
+                expr(ty, scope.with(err_name.clone(), Ty::UInt), depth),
+            )
+                .prop_map(move |(r, ok, err)| {
+                    format!("(match {r} {ok_name} {ok} {err_name} {err})")
+                })
+                .boxed()
+        }
+        Form::Arithmetic => (select(vec!["+", "-", "*", "/"]), sub(ty), sub(ty))
+            .prop_map(|(op, a, b)| format!("({op} {a} {b})"))
+            .boxed(),
+        Form::Fold => (0..HELPERS, sub(Ty::ListInt), sub(Ty::Int))
+            .prop_map(|(i, list, init)| format!("(fold fold-{i} {list} {init})"))
+            .boxed(),
+        Form::VarGet if ty == Ty::Int => Just("(var-get var-int)".to_string()).boxed(),
+        Form::VarGet => Just("(var-get var-uint)".to_string()).boxed(),
+        Form::MapGetDefault => (sub(Ty::Int), sub(Ty::Int))
+            .prop_map(|(default, key)| format!("(default-to {default} (map-get? map-int {key}))"))
+            .boxed(),
+        Form::Len => sub(Ty::ListInt)
+            .prop_map(|list| format!("(len {list})"))
+            .boxed(),
+        Form::Balance => Just("(ft-get-balance token tx-sender)".to_string()).boxed(),
+        Form::Compare => {
+            let scope = scope.clone();
+            (
+                select(vec![Ty::Int, Ty::UInt]),
+                select(vec!["<", ">", "<=", ">="]),
+            )
+                .prop_flat_map(move |(operand_ty, op)| {
+                    (
+                        expr(operand_ty, scope.clone(), depth),
+                        expr(operand_ty, scope.clone(), depth),
+                    )
+                        .prop_map(move |(a, b)| format!("({op} {a} {b})"))
+                })
+                .boxed()
+        }
+        Form::Logic => (select(vec!["and", "or"]), sub(Ty::Bool), sub(Ty::Bool))
+            .prop_map(|(op, a, b)| format!("({op} {a} {b})"))
+            .boxed(),
+        Form::Not => sub(Ty::Bool).prop_map(|b| format!("(not {b})")).boxed(),
+        Form::IsEq => {
+            let scope = scope.clone();
+            select(ALL_TYPES.to_vec())
+                .prop_flat_map(move |operand_ty| {
+                    (
+                        expr(operand_ty, scope.clone(), depth),
+                        expr(operand_ty, scope.clone(), depth),
+                    )
+                        .prop_map(|(a, b)| format!("(is-eq {a} {b})"))
+                })
+                .boxed()
+        }
+        Form::IsSome => sub(Ty::OptionalInt)
+            .prop_map(|o| format!("(is-some {o})"))
+            .boxed(),
+        Form::IsOk => sub(Ty::Response)
+            .prop_map(|r| format!("(is-ok {r})"))
+            .boxed(),
+        Form::VarSet => prop_oneof![
+            sub(Ty::Int).prop_map(|value| format!("(var-set var-int {value})")),
+            sub(Ty::UInt).prop_map(|value| format!("(var-set var-uint {value})")),
+        ]
+        .boxed(),
+        Form::MapSet => (
+            select(vec!["map-set", "map-insert"]),
+            sub(Ty::Int),
+            sub(Ty::Int),
+        )
+            .prop_map(|(op, key, value)| format!("({op} map-int {key} {value})"))
+            .boxed(),
+        Form::MapDelete => sub(Ty::Int)
+            .prop_map(|key| format!("(map-delete map-int {key})"))
+            .boxed(),
+        Form::Some => sub(Ty::Int)
+            .prop_map(|value| format!("(some {value})"))
+            .boxed(),
+        Form::MapGet => sub(Ty::Int)
+            .prop_map(|key| format!("(map-get? map-int {key})"))
+            .boxed(),
+        Form::ElementAt => (sub(Ty::ListInt), sub(Ty::UInt))
+            .prop_map(|(list, index)| format!("(element-at? {list} {index})"))
+            .boxed(),
+        Form::OkOrErr => (sub(Ty::Bool), sub(Ty::Bool), sub(Ty::UInt))
+            .prop_map(|(c, ok, err)| format!("(if {c} (ok {ok}) (err {err}))"))
+            .boxed(),
+        Form::Token => (select(vec!["ft-mint?", "ft-burn?"]), sub(Ty::UInt))
+            .prop_map(|(op, amount)| format!("({op} token {amount} tx-sender)"))
+            .boxed(),
+        Form::List => prop::collection::vec(sub(Ty::Int), 1..4)
+            .prop_map(|items| format!("(list {})", items.join(" ")))
+            .boxed(),
+        Form::Map => (0..HELPERS, sub(Ty::ListInt))
+            .prop_map(|(i, list)| format!("(map map-{i} {list})"))
+            .boxed(),
+    }
+}
+
+/// A contract declaring data-vars, a map, a token and private functions for
+/// `fold` and `map`, followed by top-level expressions of any type.
+pub fn prop_program() -> impl Strategy<Value = String> {
+    let fold_scope = Scope {
+        vars: vec![("x".to_string(), Ty::Int), ("acc".to_string(), Ty::Int)],
+        helpers: false,
+    };
+    let map_scope = Scope {
+        vars: vec![("x".to_string(), Ty::Int)],
+        helpers: false,
+    };
+    let top_level = Scope {
+        vars: Vec::new(),
+        helpers: true,
+    };
+    let any_expr = move || {
+        let scope = top_level.clone();
+        select(ALL_TYPES.to_vec()).prop_flat_map(move |ty| expr(ty, scope.clone(), MAX_DEPTH))
+    };
+
+    (
+        prop::collection::vec(expr(Ty::Int, fold_scope, 2), HELPERS),
+        prop::collection::vec(expr(Ty::Int, map_scope, 2), HELPERS),
+        prop::collection::vec(any_expr(), 0..3),
+        any_expr(),
+    )
+        .prop_map(|(fold_bodies, map_bodies, statements, result)| {
+            let mut program = String::from(
+                "(define-data-var var-int int 0)\n\
+                 (define-data-var var-uint uint u0)\n\
+                 (define-map map-int int int)\n\
+                 (define-fungible-token token)\n",
+            );
+            for (i, body) in fold_bodies.iter().enumerate() {
+                program.push_str(&format!(
+                    "(define-private (fold-{i} (x int) (acc int)) {body})\n"
+                ));
+            }
+            for (i, body) in map_bodies.iter().enumerate() {
+                program.push_str(&format!("(define-private (map-{i} (x int)) {body})\n"));
+            }
+            for statement in statements {
+                program.push_str(&statement);
+                program.push('\n');
+            }
+            program.push_str(&result);
+            program
+        })
+}
+
+/// Crosscheck `program`, returning the divergences, if any. Like with
+/// `crosscheck`, all errors are considered equal.
+pub fn check_program(program: &str) -> Result<(), String> {
+    let report = crosscheck_report(program);
+    let both_failed = report.compiled.result.is_err() && report.interpreted.result.is_err();
+    let divergences: Vec<_> = report
+        .divergences
+        .iter()
+        .filter(|divergence| !(both_failed && matches!(divergence, Divergence::Result { .. })))
+        .map(|divergence| divergence.to_string())
+        .collect();
+
+    if divergences.is_empty() {
+        Ok(())
+    } else {
+        Err(divergences.join("\n"))
+    }
+}
+
+proptest! {
+    #![proptest_config(super::runtime_config())]
+
+    #[test]
+    fn crosscheck_random_programs(program in prop_program()) {
+        if let Err(divergences) = check_program(&program) {
+            return Err(proptest::test_runner::TestCaseError::fail(
+                format!("{program}\n\n{divergences}")
+            ));
+        }
+    }
+}
@@ -38,3 +38,260 @@ fn test_clar2wasm_with_input() {
 
     temp.close().unwrap();
 }
+
+#[test]
+fn test_clar2wasm_with_contract_settings() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let outfile = temp.join("define-read-only-0.wasm");
+
+    assert_cmd::Command::cargo_bin("clar2wasm")
+        .unwrap()
+        .arg("./contracts/define-read-only-0.clar")
+        .args(["--clarity-version", "clarity1", "--epoch", "2.05"])
+        .args([
+            "--contract-id",
+            "S1G2081040G2081040G2081040G208105NK8PE5.define-read-only-0",
+        ])
+        .arg("-o")
+        .arg(&outfile)
+        .assert()
+        .success();
+
+    wasmparser::validate(&std::fs::read(outfile).unwrap()).unwrap();
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn test_clar2wasm_reports_unavailable_words() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let infile = temp.join("clarity2.clar");
+    std::fs::write(&infile, "(define-read-only (sponsor) tx-sponsor?)").unwrap();
+
+    assert_cmd::Command::cargo_bin("clar2wasm")
+        .unwrap()
+        .arg(&infile)
+        .args(["--clarity-version", "clarity1"])
+        .assert()
+        .stderr(predicates::str::contains(
+            "'tx-sponsor?' is not available in Clarity 1",
+        ))
+        .failure();
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn test_clar2wasm_emit_wat() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let outfile = temp.join("define-read-only-0.wasm");
+
+    assert_cmd::Command::cargo_bin("clar2wasm")
+        .unwrap()
+        .arg("./contracts/define-read-only-0.clar")
+        .args(["--emit", "both"])
+        .arg("-o")
+        .arg(&outfile)
+        .assert()
+        .stdout(predicates::str::contains(
+            ";; (define-read-only (simple) 42)",
+        ))
+        .stdout(predicates::str::contains("(func $simple"))
+        .success();
+
+    wasmparser::validate(&std::fs::read(outfile).unwrap()).unwrap();
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn test_clar2wasm_keep_stdlib() {
+    use predicates::prelude::PredicateBooleanExt;
+
+    let temp = assert_fs::TempDir::new().unwrap();
+    let outfile = temp.join("define-read-only-0.wasm");
+
+    assert_cmd::Command::cargo_bin("clar2wasm")
+        .unwrap()
+        .arg("./contracts/define-read-only-0.clar")
+        .args(["--emit", "wat"])
+        .arg("-o")
+        .arg(&outfile)
+        .assert()
+        .stdout(predicates::str::contains("(func $stdlib.sha256-buf").not())
+        .success();
+
+    assert_cmd::Command::cargo_bin("clar2wasm")
+        .unwrap()
+        .arg("./contracts/define-read-only-0.clar")
+        .args(["--emit", "wat", "--keep-stdlib"])
+        .arg("-o")
+        .arg(&outfile)
+        .assert()
+        .stdout(predicates::str::contains("(func $stdlib.sha256-buf"))
+        .success();
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn test_clar2wasm_project_directory() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let project = temp.join("project");
+    let output = temp.join("build");
+    std::fs::create_dir(&project).unwrap();
+    std::fs::copy(
+        "./contracts/contract-callee.clar",
+        project.join("contract-callee.clar"),
+    )
+    .unwrap();
+    std::fs::copy(
+        "./contracts/multi-contract/contract-caller.clar",
+        project.join("contract-caller.clar"),
+    )
+    .unwrap();
+
+    assert_cmd::Command::cargo_bin("clar2wasm")
+        .unwrap()
+        .arg(&project)
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    for contract in ["contract-callee", "contract-caller"] {
+        let wasm = std::fs::read(output.join(format!("{contract}.wasm"))).unwrap();
+        wasmparser::validate(&wasm).unwrap();
+    }
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn test_clar2wasm_project_cycle() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    std::fs::write(
+        temp.join("a.clar"),
+        "(define-public (call) (contract-call? .b call))",
+    )
+    .unwrap();
+    std::fs::write(
+        temp.join("b.clar"),
+        "(define-public (call) (contract-call? .a call))",
+    )
+    .unwrap();
+    std::fs::write(
+        temp.join("Clarinet.toml"),
+        "[contracts.a]\npath = \"a.clar\"\n\n[contracts.b]\npath = \"b.clar\"\n",
+    )
+    .unwrap();
+
+    assert_cmd::Command::cargo_bin("clar2wasm")
+        .unwrap()
+        .arg(temp.join("Clarinet.toml"))
+        .assert()
+        .stderr(predicates::str::contains("dependency cycle: a -> b -> a"))
+        .failure();
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn test_clar2wasm_json_diagnostics() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let infile = temp.join("type-error.clar");
+    std::fs::write(&infile, "(define-read-only (foo)\n  (+ 1 u1))").unwrap();
+
+    let output = assert_cmd::Command::cargo_bin("clar2wasm")
+        .unwrap()
+        .arg(&infile)
+        .args(["--message-format", "json"])
+        .assert()
+        .failure()
+        .get_output()
+        .stdout
+        .clone();
+
+    let line = String::from_utf8(output).unwrap();
+    let diagnostic: serde_json::Value = serde_json::from_str(line.lines().next().unwrap()).unwrap();
+    assert_eq!(diagnostic["level"], "error");
+    assert_eq!(diagnostic["file"], infile.to_str().unwrap());
+    assert_eq!(diagnostic["spans"][0]["start_line"], 2);
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn test_clar2wasm_run() {
+    assert_cmd::Command::cargo_bin("clar2wasm")
+        .unwrap()
+        .args(["run", "./contracts/call-public-with-args.clar"])
+        .args(["--function", "simple", "--arg", "1", "--arg", "2"])
+        .assert()
+        .stdout(predicates::str::contains("result: (ok 3)"))
+        .stdout(predicates::str::contains("committed: true"))
+        .success();
+}
+
+#[test]
+fn test_clar2wasm_run_events() {
+    assert_cmd::Command::cargo_bin("clar2wasm")
+        .unwrap()
+        .args(["run", "./contracts/print.clar", "--function", "print-int"])
+        .assert()
+        .stdout(predicates::str::contains("print"))
+        .stdout(predicates::str::contains("12345"))
+        .success();
+}
+
+#[test]
+fn test_clar2wasm_run_unknown_function() {
+    assert_cmd::Command::cargo_bin("clar2wasm")
+        .unwrap()
+        .args(["run", "./contracts/print.clar", "--function", "missing"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_crosscheck_directory() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    std::fs::copy("./contracts/add-3.clar", temp.join("add-3.clar")).unwrap();
+    std::fs::write(
+        temp.join("invalid.clar"),
+        "(define-public (f) (ok (+ 1 u1)))",
+    )
+    .unwrap();
+    std::fs::write(temp.join("notes.txt"), "not a contract").unwrap();
+    let report = temp.join("report.json");
+
+    assert_cmd::Command::cargo_bin("crosscheck")
+        .unwrap()
+        .arg(temp.path())
+        .arg("--json")
+        .arg(&report)
+        .assert()
+        .stdout(predicates::str::contains(
+            "1 passed, 0 diverged, 1 compile errors",
+        ))
+        .success();
+
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(report).unwrap()).unwrap();
+    assert_eq!(report["passed"], 1);
+    assert_eq!(report["contracts"][0]["status"], "pass");
+    assert_eq!(report["contracts"][1]["status"], "compile-error");
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn test_crosscheck_glob() {
+    assert_cmd::Command::cargo_bin("crosscheck")
+        .unwrap()
+        .arg("./contracts/add-*.clar")
+        .assert()
+        .stdout(predicates::str::contains("add-3.clar"))
+        .stdout(predicates::str::contains("1 passed"))
+        .success();
+}